@@ -0,0 +1,43 @@
+//! Saving and resuming permutation progress
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Permutations performed so far by a test
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// [`crate::runner::config_hash`] of the test
+    pub config: String,
+    pub seed: Option<u64>,
+    pub perms: Vec<u64>,
+}
+
+impl Checkpoint {
+    pub fn new(config: String, seed: Option<u64>) -> Self {
+        Checkpoint {
+            config,
+            seed,
+            perms: vec![],
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        serde_json::from_reader(BufReader::new(file)).map_err(io::Error::from)
+    }
+
+    /// Write to a temporary file then move it over the checkpoint so that
+    /// being killed mid-write leaves the previous checkpoint intact
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp = PathBuf::from(path);
+        tmp.set_extension("tmp");
+        {
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            serde_json::to_writer(&mut out, self)?;
+            out.flush()?;
+        }
+        std::fs::rename(&tmp, path)
+    }
+}
@@ -1,49 +1,99 @@
 //! Command line argument parser
 extern crate pretty_env_logger;
 
-use crate::overlappers::Overlapper;
+use crate::io::{Payload, STDIO};
+use crate::overlappers::{CountMethod, Overlapper};
+#[cfg(feature = "plot")]
+use crate::plot::{Panel, PlotFormat, PlotOpts};
 use crate::randomizers::Randomizer;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Serialize;
 
 #[derive(Parser)]
 #[command(author = "ACEnglish", version)]
 pub struct ArgParser {
-    /// chromosome lengths (chrom\tlength)
-    #[arg(short, long)]
-    pub genome: std::path::PathBuf,
+    #[command(subcommand)]
+    pub command: Command,
+}
 
-    /// bed file of regions (chrom\tstart\tend)
-    #[arg(short = 'A')]
-    pub bed_a: std::path::PathBuf,
+#[derive(Subcommand)]
+pub enum Command {
+    /// Permutation test of A's overlap with B
+    Test(TestArgs),
+    /// Write randomized copies of a bed file
+    Shuffle(ShuffleArgs),
+    /// Recalculate local z-scores of an existing test
+    Localz(LocalzArgs),
+    /// Combine the permutations of tests run as independent shards
+    Merge(MergeArgs),
+    /// Plot an existing test's results
+    #[cfg(feature = "plot")]
+    Plot(PlotArgs),
+    /// Pairwise permutation tests between many bed files
+    Matrix(MatrixArgs),
+}
 
-    /// bed file of regions (chrom\tstart\tend)
-    #[arg(short = 'B')]
-    pub bed_b: std::path::PathBuf,
+/// Input exists or is stdin
+fn is_input(path: &std::path::Path) -> bool {
+    path == std::path::Path::new(STDIO) || path.is_file()
+}
 
-    /// number of permutations to perform
-    #[arg(short, long = "num-times", default_value_t = 100)]
-    pub num_times: u32,
+/// A and B exist and at most one is read from stdin
+fn check_pair(bed_a: &std::path::Path, bed_b: &std::path::Path) -> bool {
+    let mut is_ok = true;
+    if !is_input(bed_a) {
+        error!("-A file doesn't exist");
+        is_ok = false;
+    }
+    if !is_input(bed_b) {
+        error!("-B file doesn't exist");
+        is_ok = false;
+    }
+    if (bed_a == std::path::Path::new(STDIO)) & (bed_b == std::path::Path::new(STDIO)) {
+        error!("only one of -A and -B can be read from stdin");
+        is_ok = false;
+    }
+    is_ok
+}
 
-    /// output json file
+/// Genome space over which regions are randomized
+#[derive(Args)]
+pub struct GenomeArgs {
+    /// chromosome lengths (chrom\tlength)
     #[arg(short, long)]
-    pub output: std::path::PathBuf,
+    pub genome: std::path::PathBuf,
 
-    /// number of threads to use
-    #[arg(short, long, default_value_t = 1)]
-    pub threads: u8,
+    /// bed file of genome regions to mask (chrom\tstart\tend)
+    #[arg(long)]
+    pub mask: Option<std::path::PathBuf>,
+}
 
+impl GenomeArgs {
+    /// Validate command line arguments
+    pub fn validate(&self) -> bool {
+        let mut is_ok = true;
+        if !self.genome.is_file() {
+            error!("--genome file doesn't exist");
+            is_ok = false;
+        }
+
+        if let Some(m) = &self.mask {
+            if !m.is_file() {
+                error!("--mask file doesn't exist");
+                is_ok = false;
+            }
+        }
+        is_ok
+    }
+}
+
+/// How regions are randomized
+#[derive(Args)]
+pub struct RandomArgs {
     /// randomization strategy
     #[arg(value_enum, long, default_value_t = Randomizer::Shuffle)]
     pub random: Randomizer,
 
-    /// overlap counting strategy
-    #[arg(value_enum, long, default_value_t = Overlapper::All)]
-    pub count: Overlapper,
-
-    /// bed file of genome regions to mask (chrom\tstart\tend)
-    #[arg(long)]
-    pub mask: Option<std::path::PathBuf>,
-
     /// randomize regions within each chromosome
     #[arg(long = "per-chrom", default_value_t = false)]
     pub per_chrom: bool,
@@ -52,9 +102,125 @@ pub struct ArgParser {
     #[arg(long = "no-merge-ovl", default_value_t = false)]
     pub no_merge: bool,
 
+    /// size of the genome blocks permuted by `--random block`
+    #[arg(long = "block-size", default_value_t = 100_000)]
+    pub block_size: u64,
+
+    /// bed file of regions randomized intervals aren't placed on (shuffle and novl)
+    #[arg(long)]
+    pub exclude: Option<std::path::PathBuf>,
+}
+
+impl RandomArgs {
+    /// Validate command line arguments
+    pub fn validate(&self) -> bool {
+        if (self.random == Randomizer::Novl) & self.no_merge {
+            warn!("using `novl` without merged overlaps may cause errors");
+            return false;
+        }
+        if self.block_size < 1 {
+            error!("--block-size must be at least 1");
+            return false;
+        }
+        if let Some(e) = &self.exclude {
+            if !e.is_file() {
+                error!("--exclude file doesn't exist");
+                return false;
+            }
+            if !matches!(self.random, Randomizer::Shuffle | Randomizer::Novl) {
+                error!("--exclude can only be used with `shuffle` and `novl`");
+                return false;
+            }
+        }
+        true
+    }
+
+    /// `--block-size` when it's used
+    pub fn block_size(&self) -> Option<u64> {
+        (self.random == Randomizer::Block).then_some(self.block_size)
+    }
+}
+
+/// Permutation settings
+#[derive(Args)]
+pub struct PermArgs {
+    /// number of permutations to perform
+    #[arg(short, long = "num-times", default_value_t = 100)]
+    pub num_times: u32,
+
+    /// number of threads to use (0 for all cores)
+    #[arg(short, long, default_value_t = 1)]
+    pub threads: usize,
+
+    /// random seed (default: from the clock)
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// overlap counting strategy
+    #[arg(value_enum, long, default_value_t = Overlapper::All)]
+    pub count: Overlapper,
+
+    /// how overlaps are found
+    #[arg(value_enum, long = "count-method", default_value_t = CountMethod::Auto)]
+    pub count_method: CountMethod,
+
     /// do not swap A and B
     #[arg(long = "no-swap", default_value_t = false)]
     pub no_swap: bool,
+}
+
+impl PermArgs {
+    /// Validate command line arguments
+    pub fn validate(&self) -> bool {
+        if self.num_times < 100 {
+            warn!(
+                "minimum p-value with {} is {}.",
+                self.num_times,
+                1.0 / ((self.num_times as f32) + 1.0)
+            );
+        }
+        true
+    }
+
+    /// Number of threads to run, resolving 0 to all cores
+    pub fn num_threads(&self) -> usize {
+        match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+    }
+}
+
+/// Column of A whose categories are tested separately
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    /// the bed's name (column 4)
+    Name,
+}
+
+#[derive(Args)]
+pub struct TestArgs {
+    #[command(flatten)]
+    pub genome: GenomeArgs,
+
+    /// bed file of regions (chrom\tstart\tend) or `-` for stdin
+    #[arg(short = 'A')]
+    pub bed_a: std::path::PathBuf,
+
+    /// bed file of regions (chrom\tstart\tend) or `-` for stdin
+    #[arg(short = 'B')]
+    pub bed_b: std::path::PathBuf,
+
+    /// output json file (or `-` for stdout)
+    #[arg(short, long)]
+    pub output: std::path::PathBuf,
+
+    #[command(flatten)]
+    pub perm: PermArgs,
+
+    #[command(flatten)]
+    pub random: RandomArgs,
 
     /// local Z-score window size
     #[arg(long, default_value_t = 1000)]
@@ -63,50 +229,346 @@ pub struct ArgParser {
     /// local Z-score window step
     #[arg(long, default_value_t = 50)]
     pub step: u64,
+
+    /// randomize and test each category of A separately, plus their pooled test
+    #[arg(value_enum, long = "group-by")]
+    pub group_by: Option<GroupBy>,
+
+    /// write the first N randomized A sets as bed files to DIR
+    #[arg(long = "dump-perms", num_args = 2, value_names = ["N", "DIR"])]
+    pub dump_perms: Option<Vec<String>>,
+
+    /// also write a tab-delimited summary of the test
+    #[arg(long = "summary-tsv")]
+    pub summary_tsv: Option<std::path::PathBuf>,
+
+    /// periodically save permutations to this file
+    #[arg(long)]
+    pub checkpoint: Option<std::path::PathBuf>,
+
+    /// number of permutations between checkpoints
+    #[arg(long = "checkpoint-every", default_value_t = 1000)]
+    pub checkpoint_every: u32,
+
+    /// continue from the permutations in --checkpoint
+    #[arg(long, default_value_t = false, requires = "checkpoint")]
+    pub resume: bool,
+
+    /// don't plot the results
+    #[arg(long = "no-plot", default_value_t = false)]
+    pub no_plot: bool,
+
+    /// plot path prefix (default: output path without extension)
+    #[arg(long = "plot-prefix")]
+    pub plot_prefix: Option<std::path::PathBuf>,
+
+    /// plot file format
+    #[cfg(feature = "plot")]
+    #[arg(value_enum, long = "plot-format", default_value_t = PlotFormat::Png)]
+    pub plot_format: PlotFormat,
 }
 
-impl ArgParser {
+impl TestArgs {
     /// Validate command line arguments
     pub fn validate(&self) -> bool {
-        let mut is_ok = true;
-        if !self.bed_a.is_file() {
-            error!("-A file doesn't exist");
+        let mut is_ok = self.genome.validate() & self.random.validate() & self.perm.validate();
+        is_ok &= check_pair(&self.bed_a, &self.bed_b);
+
+        if let Some(t) = &self.summary_tsv {
+            if (t == std::path::Path::new(STDIO)) & (self.output == std::path::Path::new(STDIO)) {
+                error!("only one of --output and --summary-tsv can be written to stdout");
+                is_ok = false;
+            }
+        }
+
+        if self.checkpoint_every < 1 {
+            error!("--checkpoint-every must be at least 1");
             is_ok = false;
         }
-        if !self.bed_b.is_file() {
-            error!("-B file doesn't exist");
+        if let Some(c) = &self.checkpoint {
+            if self.resume & !c.is_file() {
+                error!("--checkpoint file doesn't exist to --resume");
+                is_ok = false;
+            }
+        }
+
+        if let Some(d) = &self.dump_perms {
+            if d[0].parse::<u32>().is_err() {
+                error!("--dump-perms N must be a number");
+                is_ok = false;
+            }
+            if !std::path::Path::new(&d[1]).is_dir() {
+                error!("--dump-perms DIR doesn't exist");
+                is_ok = false;
+            }
+        }
+
+        if (self.random.random == Randomizer::Novl)
+            & (self.perm.count.payload_a() == Payload::Score)
+        {
+            error!("`novl` can't place the overlapping regions of a --count that reads A's scores");
             is_ok = false;
         }
-        if !self.genome.is_file() {
-            error!("--genome file doesn't exist");
+
+        if self.group_by.is_some() {
+            if self.perm.count.payload_a() != Payload::Empty {
+                error!("--group-by can't be used with a --count that reads A's scores");
+                is_ok = false;
+            }
+            if self.checkpoint.is_some() | self.dump_perms.is_some() {
+                error!("--group-by can't be used with --checkpoint or --dump-perms");
+                is_ok = false;
+            }
+        }
+
+        is_ok
+    }
+
+    /// Plotting options
+    #[cfg(feature = "plot")]
+    pub fn plot_opts(&self) -> PlotOpts {
+        PlotOpts {
+            format: self.plot_format,
+            ..Default::default()
+        }
+    }
+
+    /// Number of permutations to dump and the directory to write them in
+    pub fn dump_perms(&self) -> (u32, std::path::PathBuf) {
+        match &self.dump_perms {
+            Some(d) => (d[0].parse().unwrap(), std::path::PathBuf::from(&d[1])),
+            None => (0, std::path::PathBuf::new()),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ShuffleArgs {
+    #[command(flatten)]
+    pub genome: GenomeArgs,
+
+    /// bed file of regions (chrom\tstart\tend) or `-` for stdin
+    #[arg(short = 'A')]
+    pub bed_a: std::path::PathBuf,
+
+    /// number of randomized bed files to write
+    #[arg(short, long = "num-times", default_value_t = 1)]
+    pub num_times: u32,
+
+    /// output directory (or `-` for stdout with one randomization)
+    #[arg(short, long)]
+    pub output: std::path::PathBuf,
+
+    #[command(flatten)]
+    pub random: RandomArgs,
+
+    /// random seed (default: from the clock)
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
+
+impl ShuffleArgs {
+    /// Validate command line arguments
+    pub fn validate(&self) -> bool {
+        let mut is_ok = self.genome.validate() & self.random.validate();
+        if !is_input(&self.bed_a) {
+            error!("-A file doesn't exist");
             is_ok = false;
         }
 
-        if let Some(m) = &self.mask {
-            if !m.is_file() {
-                error!("--mask file doesn't exist");
+        if self.output == std::path::Path::new(STDIO) {
+            if self.num_times != 1 {
+                error!("only one randomized bed can be written to stdout");
                 is_ok = false;
             }
+        } else if !self.output.is_dir() {
+            error!("--output directory doesn't exist");
+            is_ok = false;
         }
 
-        if self.threads < 1 {
-            warn!("need at least 1 thread");
+        is_ok
+    }
+}
+
+#[derive(Args)]
+pub struct LocalzArgs {
+    /// json output of `regioners test`
+    pub results: std::path::PathBuf,
+
+    #[command(flatten)]
+    pub genome: GenomeArgs,
+
+    /// bed file of regions (chrom\tstart\tend) or `-` for stdin
+    #[arg(short = 'A')]
+    pub bed_a: std::path::PathBuf,
+
+    /// bed file of regions (chrom\tstart\tend) or `-` for stdin
+    #[arg(short = 'B')]
+    pub bed_b: std::path::PathBuf,
+
+    /// output json file (or `-` for stdout)
+    #[arg(short, long)]
+    pub output: std::path::PathBuf,
+
+    /// local Z-score window size
+    #[arg(long, default_value_t = 1000)]
+    pub window: i64,
+
+    /// local Z-score window step
+    #[arg(long, default_value_t = 50)]
+    pub step: u64,
+}
+
+impl LocalzArgs {
+    /// Validate command line arguments
+    pub fn validate(&self) -> bool {
+        let mut is_ok = self.genome.validate();
+        if !self.results.is_file() {
+            error!("results file doesn't exist");
             is_ok = false;
         }
+        is_ok &= check_pair(&self.bed_a, &self.bed_b);
+        is_ok
+    }
+}
 
-        if (self.random == Randomizer::Novl) & self.no_merge {
-            warn!("using `novl` without merged overlaps may cause errors");
+#[derive(Args)]
+pub struct MergeArgs {
+    /// json outputs of `regioners test` shards over the same inputs
+    #[arg(num_args = 2.., required = true)]
+    pub results: Vec<std::path::PathBuf>,
+
+    /// output json file (or `-` for stdout)
+    #[arg(short, long)]
+    pub output: std::path::PathBuf,
+}
+
+impl MergeArgs {
+    /// Validate command line arguments
+    pub fn validate(&self) -> bool {
+        let mut is_ok = true;
+        for i in &self.results {
+            if !i.is_file() {
+                error!("results file {} doesn't exist", i.display());
+                is_ok = false;
+            }
+        }
+        is_ok
+    }
+}
+
+#[cfg(feature = "plot")]
+#[derive(Args)]
+pub struct PlotArgs {
+    /// json output of `regioners test`. Multiple files or a json list of results are plotted as a batch
+    #[arg(num_args = 1.., required = true)]
+    pub results: Vec<std::path::PathBuf>,
+
+    /// plot path prefix (default: first results path without extension)
+    #[arg(short, long)]
+    pub prefix: Option<std::path::PathBuf>,
+
+    /// plot width in pixels
+    #[arg(long, default_value_t = crate::plot::W)]
+    pub width: u32,
+
+    /// plot height in pixels
+    #[arg(long, default_value_t = crate::plot::H)]
+    pub height: u32,
+
+    /// plot title
+    #[arg(long)]
+    pub title: Option<String>,
+
+    /// plots to draw (default: perms,localz,summary or forest,volcano for batches)
+    #[arg(value_enum, long, value_delimiter = ',')]
+    pub panels: Option<Vec<Panel>>,
+
+    /// plot file format
+    #[arg(value_enum, long = "plot-format", default_value_t = PlotFormat::Png)]
+    pub plot_format: PlotFormat,
+}
+
+#[cfg(feature = "plot")]
+impl PlotArgs {
+    /// Validate command line arguments
+    pub fn validate(&self) -> bool {
+        let mut is_ok = true;
+        for r in &self.results {
+            if !r.is_file() {
+                error!("results file {} doesn't exist", r.display());
+                is_ok = false;
+            }
+        }
+        // leave room for the margins and axis labels
+        if (self.width < 400) | (self.height < 400) {
+            error!("--width and --height must be at least 400");
             is_ok = false;
         }
+        is_ok
+    }
 
-        if self.num_times < 100 {
-            warn!(
-                "minimum p-value with {} is {}.",
-                self.num_times,
-                1.0 / ((self.num_times as f32) + 1.0)
-            );
+    /// Plotting options
+    pub fn opts(&self, batch: bool) -> PlotOpts {
+        let panels = match (&self.panels, batch) {
+            (Some(p), _) => p.clone(),
+            (None, false) => vec![Panel::Perms, Panel::Localz, Panel::Summary],
+            (None, true) => vec![Panel::Forest, Panel::Volcano],
+        };
+        PlotOpts {
+            width: self.width,
+            height: self.height,
+            title: self.title.clone(),
+            panels,
+            format: self.plot_format,
         }
+    }
+}
+
+#[derive(Args)]
+pub struct MatrixArgs {
+    #[command(flatten)]
+    pub genome: GenomeArgs,
+
+    /// bed files of regions (chrom\tstart\tend)
+    #[arg(short, long, num_args = 2.., required = true)]
+    pub input: Vec<std::path::PathBuf>,
 
+    /// output json file (or `-` for stdout)
+    #[arg(short, long)]
+    pub output: std::path::PathBuf,
+
+    #[command(flatten)]
+    pub perm: PermArgs,
+
+    #[command(flatten)]
+    pub random: RandomArgs,
+
+    /// also write a tab-delimited summary with a row per test
+    #[arg(long = "summary-tsv")]
+    pub summary_tsv: Option<std::path::PathBuf>,
+}
+
+impl MatrixArgs {
+    /// Validate command line arguments
+    pub fn validate(&self) -> bool {
+        let mut is_ok = self.genome.validate() & self.random.validate() & self.perm.validate();
+        if self.perm.count == Overlapper::Signal {
+            error!("--count signal needs a bedGraph -B and can't be used with matrix");
+            is_ok = false;
+        }
+        if (self.random.random == Randomizer::Novl)
+            & (self.perm.count.payload_b() == Payload::Score)
+        {
+            error!("`novl` can't place the overlapping regions of a --count that reads scores");
+            is_ok = false;
+        }
+        for i in &self.input {
+            if !i.is_file() {
+                error!("--input file {} doesn't exist", i.display());
+                is_ok = false;
+            }
+        }
         is_ok
     }
 }
@@ -1,54 +1,98 @@
 //! Helper for breaking gaps during novl randomization
-use tinyrand::{Rand, RandRange, Seeded, StdRand};
-use tinyrand_std::ClockSeed;
+use std::collections::HashSet;
 
-/// When performing novl randomization, we break the uncovered spans of
-/// the genome into pieces and shuffle them along with the intervals.
-/// Truly random novl would break all the uncovered spans into 1bp pieces.
-/// However, this is extremly inefficient. Instead, we break it into pieces
-/// randomly between 1bp and some maximum size. If this maximum size was
-/// the length of all uncovered spans, we could end up making one giant
-/// uncovered span and then grouping all the intervals end-to-end.
-/// By setting the maximum size to some percent of the uncovered span
-/// we lower the bias towards making large gaps.
-/// NOVLMAGIC is calculated as `int(1 / max_size_percent)`
-const NOVLMAGIC: u64 = 10000;
+use tinyrand::{RandRange, StdRand};
 
-/// Holds the length of uncovered spans in a genome and a private random
-/// number generator. Can be iterated to generate random sized gaps.
-/// Gaps are returned as `(false, size)` tuples so they be differentiated
-/// from covered intervals (`(true, size)`) in [`randomizers::novl_intervals`]
+/// When performing novl randomization, the uncovered bases of a span are split
+/// into a gap before each interval plus one after the last. Every way of splitting
+/// them is equally likely, so along with a random order of the intervals, every
+/// non-overlapping placement of the intervals is equally likely.
+///
+/// The gaps are drawn by stars and bars: splitting `total_gap_size` bases into
+/// `n + 1` gaps is the same as picking which `n` of `total_gap_size + n` slots
+/// are bars, so `n` distinct slots are sampled (Floyd's algorithm) and sorted.
+/// The bases between consecutive bars are the gaps.
+///
+/// Holds buffers reused between samples.
 ///
 /// Example:
-///  
+///
 /// ```
-/// let mut gapsizes: Vec<(bool, u64)> = GapBreaks::new(100).collect();
+/// let mut rand = StdRand::seed(1);
+/// let mut breaks = GapBreaks::default();
+/// // gaps before each of 3 intervals sharing 100 uncovered bases
+/// let gaps: Vec<u64> = breaks.sample(100, 3, &mut rand).collect();
 /// ```
+#[derive(Default)]
 pub struct GapBreaks {
-    total_gap_size: u64,
-    rand: StdRand,
+    bars: Vec<u64>,
+    seen: HashSet<u64>,
 }
 
 impl GapBreaks {
-    pub fn new(total_gap_size: u64) -> Self {
-        Self {
-            total_gap_size,
-            rand: StdRand::seed(ClockSeed::default().next_u64()),
+    /// Sample the gaps before each of `num_intervals` intervals sharing `total_gap_size`
+    /// uncovered bases. The gap after the last interval is the bases left over
+    pub fn sample(
+        &mut self,
+        total_gap_size: u64,
+        num_intervals: usize,
+        rand: &mut StdRand,
+    ) -> impl Iterator<Item = u64> + '_ {
+        let n = num_intervals as u64;
+        let slots = total_gap_size + n;
+        self.seen.clear();
+        for j in (slots - n)..slots {
+            let t = rand.next_range(0..(j + 1));
+            if !self.seen.insert(t) {
+                self.seen.insert(j);
+            }
         }
+        self.bars.clear();
+        self.bars.extend(self.seen.iter());
+        self.bars.sort_unstable();
+
+        // the kth bar has k bars before it, the rest of the slots before it are gap bases
+        let mut prev = 0;
+        self.bars.iter().enumerate().map(move |(k, &b)| {
+            let pos = b - k as u64;
+            let gap = pos - prev;
+            prev = pos;
+            gap
+        })
     }
 }
 
-impl Iterator for GapBreaks {
-    type Item = (bool, u64);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::looks_uniform;
+    use std::collections::HashMap;
+    use tinyrand::Seeded;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.total_gap_size > 0 {
-            let g_l = self
-                .rand
-                .next_range(1..std::cmp::max(2, self.total_gap_size / NOVLMAGIC));
-            self.total_gap_size -= g_l;
-            return Some((false, g_l));
+    #[test]
+    fn sample_is_uniform() {
+        // 13 uncovered bases split around 2 intervals: C(15, 2) = 105 ways
+        let mut rand = StdRand::seed(48);
+        let mut breaks = GapBreaks::default();
+        let num = 21_000;
+        let mut seen = HashMap::new();
+        for _ in 0..num {
+            let gaps: Vec<u64> = breaks.sample(13, 2, &mut rand).collect();
+            assert_eq!(gaps.len(), 2);
+            assert!(gaps.iter().sum::<u64>() <= 13);
+            *seen.entry(gaps).or_insert(0) += 1;
         }
-        None
+        assert!(looks_uniform(seen.values().copied(), 105, num));
+    }
+
+    #[test]
+    fn sample_edges() {
+        let mut rand = StdRand::seed(48);
+        let mut breaks = GapBreaks::default();
+        assert_eq!(
+            breaks.sample(0, 3, &mut rand).collect::<Vec<_>>(),
+            [0, 0, 0]
+        );
+        assert_eq!(breaks.sample(5, 0, &mut rand).count(), 0);
     }
 }
@@ -1,12 +1,15 @@
 //! Input file parsers
 extern crate pretty_env_logger;
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, BufWriter, Read, Write};
 use std::path::Path;
+use std::rc::Rc;
 
 use rust_lapper::{Interval, Lapper};
+use serde::Serialize;
 
 pub type Iv = Interval<u64, u64>;
 pub type MaskShift = HashMap<String, Lapper<u64, u64>>;
@@ -20,9 +23,24 @@ pub struct GenomeShift {
     // total span of the genome
     pub span: u64,
     pub gap_budget: Option<HashMap<u64, u64>>,
+    // blocks the block randomizer permutes. val is the end of the block's chromosome
+    pub blocks: Option<Vec<Iv>>,
+    // merged regions randomized intervals can't be placed on
+    pub exclude: Option<Lapper<u64, u64>>,
+    // chrom start in concatenated genome coordinates : chrom name
+    pub names: HashMap<u64, String>,
+    // masked regions removed when building the concatenated genome
+    pub mask: Option<MaskShift>,
+    // where each stretch of the concatenated genome that's contiguous in the reference starts.
+    // i.e. chromosome starts and the junctions left by masked regions
+    pub segments: Vec<u64>,
+    // chrom start in concatenated genome coordinates : length of the chromosome's longest segment
+    pub longest_segment: HashMap<u64, u64>,
 }
 
 impl GenomeShift {
+    /// Uncovered bases novl spreads between the intervals of the genome (or of each chromosome with
+    /// `per_chrom`) keyed by its start
     pub fn make_gap_budget(&mut self, intervals: &Lapper<u64, u64>, per_chrom: &bool) {
         let mut ret = HashMap::<u64, u64>::new();
         match per_chrom {
@@ -31,117 +49,350 @@ impl GenomeShift {
             }
             true => {
                 for i in self.chrom.iter() {
-                    ret.insert(
-                        i.start,
-                        intervals
-                            .find(i.start, i.stop)
-                            .map(|p| p.stop - p.start)
-                            .sum(),
-                    );
+                    let covered: u64 = intervals
+                        .find(i.start, i.stop)
+                        .map(|p| p.stop - p.start)
+                        .sum();
+                    ret.insert(i.start, i.val.saturating_sub(covered));
                 }
             }
         }
         self.gap_budget = Some(ret);
     }
+
+    /// Length of the longest segment on the chromosome starting at `chrom_start` or, given None, in the genome
+    pub fn longest_segment(&self, chrom_start: Option<u64>) -> u64 {
+        match chrom_start {
+            Some(c) => self.longest_segment.get(&c).copied().unwrap_or(0),
+            None => self.longest_segment.values().copied().max().unwrap_or(0),
+        }
+    }
+
+    /// Start of the first segment after `pos` that begins before `stop`
+    pub fn next_segment(&self, pos: u64, stop: u64) -> Option<u64> {
+        let idx = self.segments.partition_point(|&s| s <= pos);
+        self.segments.get(idx).copied().filter(|&s| s < stop)
+    }
+
+    /// Cut each chromosome into blocks of `block_size` for the block randomizer.
+    /// The end of a chromosome too short to fill a block isn't cut
+    pub fn make_blocks(&mut self, block_size: u64) {
+        let mut ret = vec![];
+        for c in self.chrom.iter() {
+            ret.extend((0..(c.stop - c.start) / block_size).map(|k| Iv {
+                start: c.start + k * block_size,
+                stop: c.start + (k + 1) * block_size,
+                val: c.stop,
+            }));
+        }
+        if ret.is_empty() {
+            warn!("every chromosome is shorter than --block-size. no intervals will move");
+        }
+        info!("cut genome into {} blocks", ret.len());
+        self.blocks = Some(ret);
+    }
+
+    /// Convert a span in concatenated genome coordinates back to reference coordinates.
+    /// Masked regions are re-expanded. Spans crossing a chromosome junction or a masked
+    /// region are split into one piece per contiguous reference span.
+    pub fn unshift(&self, start: u64, stop: u64) -> Vec<(String, u64, u64)> {
+        let mut ret = vec![];
+        for c in self.chrom.find(start, stop) {
+            let l_start = std::cmp::max(start, c.start) - c.start;
+            let l_stop = std::cmp::min(stop, c.stop) - c.start;
+            // empty (e.g. fully masked) chromosomes share their start, and so their name, with the next one
+            if l_start == l_stop {
+                continue;
+            }
+            let chrom = &self.names[&c.start];
+            let masks = match &self.mask {
+                Some(m) => m.get(chrom),
+                None => None,
+            };
+            let Some(masks) = masks else {
+                ret.push((chrom.clone(), l_start, l_stop));
+                continue;
+            };
+
+            // walk the masked regions, emitting the pieces between them
+            let mut l_shift = 0;
+            let mut cur = l_start;
+            for m in masks.iter() {
+                let m_pos = m.start - l_shift;
+                if m_pos >= l_stop {
+                    break;
+                }
+                if m_pos > cur {
+                    ret.push((chrom.clone(), cur + l_shift, m_pos + l_shift));
+                    cur = m_pos;
+                }
+                l_shift += m.stop - m.start;
+            }
+            ret.push((chrom.clone(), cur + l_shift, l_stop + l_shift));
+        }
+        ret
+    }
+}
+
+/// Path meaning stdin when reading and stdout when writing
+pub const STDIO: &str = "-";
+
+/// Passes reads through while computing their md5
+pub struct Md5Reader {
+    inner: Box<dyn Read>,
+    digest: Rc<RefCell<md5::Context>>,
+}
+
+impl Read for Md5Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.digest.borrow_mut().consume(&buf[..n]);
+        Ok(n)
+    }
 }
 
 // The output is wrapped in a Result to allow matching on errors
-// Returns an Iterator to the Reader of the lines of the file.
-type FileHandler = io::Result<io::Lines<io::BufReader<File>>>;
-pub fn read_lines<P>(filename: P) -> FileHandler
+// Returns a buffered Reader of the file (or stdin for `-`) to read lines from.
+// Everything read is added to the digest.
+type FileHandler = io::Result<io::BufReader<Md5Reader>>;
+pub fn open_reader<P>(filename: P, digest: Rc<RefCell<md5::Context>>) -> FileHandler
 where
     P: AsRef<Path>,
 {
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+    let inner: Box<dyn Read> = if filename.as_ref() == Path::new(STDIO) {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(filename)?)
+    };
+    Ok(io::BufReader::new(Md5Reader { inner, digest }))
+}
+
+/// Open a buffered output file (or stdout for `-`)
+pub fn create_output(filename: &Path) -> io::Result<Box<dyn Write>> {
+    if filename == Path::new(STDIO) {
+        Ok(Box::new(BufWriter::new(io::stdout().lock())))
+    } else {
+        Ok(Box::new(BufWriter::new(File::create(filename)?)))
+    }
+}
+
+/// What was read from an input file and what was kept
+#[derive(Serialize, Clone, Default)]
+pub struct InputStats {
+    pub path: String,
+    pub md5: String,
+    // number of entries parsed
+    pub entries: usize,
+    // number of entries kept
+    pub loaded: usize,
+    // entries dropped because their chromosome isn't in the genome
+    pub missing_chrom: usize,
+    // entries dropped because they overlap the mask
+    pub masked: usize,
+    // bases kept (for the genome, masked bases aren't counted)
+    pub bases: u64,
+    // bases removed by the mask
+    pub masked_bases: u64,
+    // categories of the name column when grouping by it
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+/// Fixed-point scale of bedGraph signal held in an interval's val
+pub const SIGNAL_SCALE: f64 = 1000.0;
+
+/// What a bed's intervals hold in their val
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Payload {
+    /// nothing (0)
+    Empty,
+    /// the bed's score (column 5) rounded to an integer
+    Score,
+    /// a bedGraph's signal (column 4) times `SIGNAL_SCALE`, rounded to an integer
+    Signal,
+    /// the index of the bed's name (column 4) in its `InputStats::groups`
+    Name,
+}
+
+impl Payload {
+    /// Column holding the value and how much it's scaled
+    fn column(&self) -> Option<(usize, f64)> {
+        match self {
+            Payload::Empty | Payload::Name => None,
+            Payload::Score => Some((5, 1.0)),
+            Payload::Signal => Some((4, SIGNAL_SCALE)),
+        }
+    }
 }
 
 struct BedParser {
     /* Read tab delimited bed files while ensuring entries have start < end.
     It also ensures entries are sorted */
     file: std::path::PathBuf,
-    prev_chrom: String,
+    prev_chrom: Rc<str>,
     prev_start: u64,
+    // each chromosome name is held once however many entries are on it
+    chroms: HashSet<Rc<str>>,
+    // names seen with Payload::Name and their index
+    groups: HashMap<String, u64>,
+    digest: Rc<RefCell<md5::Context>>,
 }
 
 impl BedParser {
     pub fn new(path: &Path) -> Self {
         Self {
             file: path.to_path_buf(),
-            prev_chrom: String::new(),
+            prev_chrom: Rc::from(""),
             prev_start: 0,
+            chroms: HashSet::new(),
+            groups: HashMap::new(),
+            digest: Rc::new(RefCell::new(md5::Context::new())),
+        }
+    }
+
+    /// Stats of the parsed file with its path, md5, and groups filled in
+    pub fn stats(&self) -> InputStats {
+        let mut groups: Vec<(&String, &u64)> = self.groups.iter().collect();
+        groups.sort_by_key(|(_, i)| **i);
+        InputStats {
+            path: self.file.display().to_string(),
+            md5: format!("{:x}", self.digest.borrow().clone().compute()),
+            groups: groups.into_iter().map(|(g, _)| g.clone()).collect(),
+            ..Default::default()
         }
     }
 
-    pub fn parse(&mut self, three_cols: bool) -> Vec<(String, u64, u64)> {
-        if let Ok(lines) = read_lines(&mut self.file) {
-            lines
-                .flatten()
-                .map(|line| {
-                    let collection: Vec<&str> = line.split('\t').collect();
-                    let n_cols = if three_cols { 3 } else { 2 };
-                    if collection.len() < n_cols {
-                        error!("malformed bed line: {}", line);
+    /// Call `each` with every entry's chromosome, start, stop, and `payload` value as it's read.
+    /// Entries without `three_cols` are genomes' (chrom, size). Returns the number of entries
+    pub fn parse(
+        &mut self,
+        three_cols: bool,
+        payload: Payload,
+        mut each: impl FnMut(&Rc<str>, u64, u64, u64),
+    ) -> usize {
+        let Ok(mut reader) = open_reader(&self.file, self.digest.clone()) else {
+            panic!("unable to read bed file");
+        };
+        let mut buf: Vec<u8> = vec![];
+        let mut num_entries = 0;
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) => break,
+                Ok(_) => (),
+                Err(e) => {
+                    error!("unable to read {}: {}", self.file.display(), e);
+                    std::process::exit(1);
+                }
+            }
+            let Ok(line) = std::str::from_utf8(&buf) else {
+                error!(
+                    "bed line isn't valid UTF-8: {}",
+                    String::from_utf8_lossy(&buf).trim_end()
+                );
+                std::process::exit(1);
+            };
+            let line = line.trim_end_matches(['\n', '\r']);
+            // bedGraph and UCSC header lines
+            if line.starts_with('#')
+                || matches!(line.split_whitespace().next(), Some("track" | "browser"))
+            {
+                continue;
+            }
+            let mut collection = line.split('\t');
+            let (Some(chrom), Some(start), stop) =
+                (collection.next(), collection.next(), collection.next())
+            else {
+                error!("malformed bed line: {}", line);
+                std::process::exit(1);
+            };
+            if three_cols && stop.is_none() {
+                error!("malformed bed line: {}", line);
+                std::process::exit(1);
+            }
+            let m_start = start.parse::<u64>().unwrap();
+            let m_stop = if three_cols {
+                stop.unwrap().parse::<u64>().unwrap()
+            } else {
+                m_start + 1
+            };
+            let m_val = match (payload, payload.column()) {
+                (Payload::Name, _) => match collection.next() {
+                    Some(name) => {
+                        let n = self.groups.len() as u64;
+                        *self.groups.entry(name.to_string()).or_insert(n)
+                    }
+                    None => {
+                        error!("line without a name in column 4: {}", line);
                         std::process::exit(1);
                     }
-                    let chrom = collection[0].to_string();
-                    let m_start = collection[1].parse::<u64>().unwrap();
-                    let m_stop = if three_cols {
-                        collection[2].parse::<u64>().unwrap()
-                    } else {
-                        m_start + 1
-                    };
-
-                    if chrom != self.prev_chrom {
-                        self.prev_chrom = chrom.clone();
-                        self.prev_start = 0;
+                },
+                // columns after the first three
+                (_, Some((col, scale))) => {
+                    match collection.nth(col - 4).map(|x| x.parse::<f64>()) {
+                        Some(Ok(x)) if x >= 0.0 => (x * scale).round() as u64,
+                        _ => {
+                            error!(
+                                "line without a non-negative value in column {}: {}",
+                                col, line
+                            );
+                            std::process::exit(1);
+                        }
                     }
+                }
+                (_, None) => 0,
+            };
 
-                    if m_stop <= m_start {
-                        error!("malformed bed line: stop <= start {}", line);
-                        std::process::exit(1);
-                    }
-                    if m_start < self.prev_start {
-                        error!(
-                            "bed file unordered `sort -k3n -k1,2n` offending line {}",
-                            line
-                        );
-                        std::process::exit(1);
+            if chrom != &*self.prev_chrom {
+                self.prev_chrom = match self.chroms.get(chrom) {
+                    Some(c) => c.clone(),
+                    None => {
+                        let c: Rc<str> = Rc::from(chrom);
+                        self.chroms.insert(c.clone());
+                        c
                     }
-                    (chrom, m_start, m_stop)
-                })
-                .collect()
-        } else {
-            panic!("unable to read bed file");
+                };
+                self.prev_start = 0;
+            }
+
+            if m_stop <= m_start {
+                error!("malformed bed line: stop <= start {}", line);
+                std::process::exit(1);
+            }
+            if m_start < self.prev_start {
+                error!(
+                    "bed file unordered `sort -k3n -k1,2n` offending line {}",
+                    line
+                );
+                std::process::exit(1);
+            }
+            each(&self.prev_chrom, m_start, m_stop, m_val);
+            num_entries += 1;
         }
+        num_entries
     }
 }
 
-pub fn read_mask(file: &Path) -> MaskShift {
+pub fn read_mask(file: &Path) -> (MaskShift, InputStats) {
     /* read bed file into Mask Shift */
     info!("parsing {}", file.display());
-    let mut load: HashMap<String, Vec<Iv>> = HashMap::new();
-    let mut num_mask = 0;
 
     let mut m_parser = BedParser::new(file);
-    for (chrom, m_start, m_stop) in m_parser.parse(true).into_iter() {
-        if !load.contains_key(&chrom) {
-            load.insert(chrom.clone(), Vec::<Iv>::new());
-        }
-
-        if let Some(val) = load.get_mut(&chrom) {
-            val.push(Iv {
-                start: m_start,
-                stop: m_stop,
-                val: 0,
-            })
-        };
-        num_mask += 1;
-    }
+    let mut load: HashMap<Rc<str>, Vec<Iv>> = HashMap::new();
+    let num_mask = m_parser.parse(true, Payload::Empty, |chrom, m_start, m_stop, _| {
+        load.entry(chrom.clone()).or_default().push(Iv {
+            start: m_start,
+            stop: m_stop,
+            val: 0,
+        })
+    });
 
     let mut ret = MaskShift::new();
-    for (key, val) in load.iter_mut() {
-        ret.insert(key.clone(), Lapper::new(val.clone()));
+    for (key, val) in load.into_iter() {
+        // overlapping masks would be double counted when shifting coordinates
+        let mut m_lap = Lapper::new(val);
+        m_lap.merge_overlaps();
+        ret.insert(key.to_string(), m_lap);
     }
 
     info!(
@@ -150,10 +401,16 @@ pub fn read_mask(file: &Path) -> MaskShift {
         ret.keys().len()
     );
 
-    ret
+    let stats = InputStats {
+        entries: num_mask,
+        loaded: num_mask,
+        masked_bases: ret.values().map(|m| m.cov()).sum(),
+        ..m_parser.stats()
+    };
+    (ret, stats)
 }
 
-pub fn read_genome(file: &Path, mask: &Option<MaskShift>) -> GenomeShift {
+pub fn read_genome(file: &Path, mask: &Option<MaskShift>) -> (GenomeShift, InputStats) {
     /*
     Read a two column genome into a GenomeShifter
     */
@@ -161,24 +418,33 @@ pub fn read_genome(file: &Path, mask: &Option<MaskShift>) -> GenomeShift {
 
     let mut load: Vec<Iv> = vec![];
     let mut m_shift: HashMap<String, u64> = HashMap::new();
+    let mut m_names: HashMap<u64, String> = HashMap::new();
     let mut cur_start: u64 = 0;
     let mut tot_masked: u64 = 0;
+    let mut segments: Vec<u64> = vec![];
 
     let mut m_parser = BedParser::new(file);
-    for (chrom, mut size, _) in m_parser.parse(false) {
+    m_parser.parse(false, Payload::Empty, |chrom, mut size, _, _| {
         let masked_bases = match mask {
-            Some(m) => {
-                if m.contains_key(&chrom) {
-                    m[&chrom].cov()
-                } else {
-                    0
-                }
-            }
-            None => 0,
+            Some(m) if m.contains_key(&**chrom) => m[&**chrom].cov(),
+            _ => 0,
         };
         size -= masked_bases;
 
-        m_shift.insert(chrom.clone(), cur_start);
+        // masked regions are removed, joining the bases on either side
+        segments.push(cur_start);
+        if let Some(m) = mask.as_ref().and_then(|m| m.get(&**chrom)) {
+            let mut l_shift = 0;
+            for i in m.iter() {
+                l_shift += i.stop - i.start;
+                segments.push(cur_start + i.stop - l_shift);
+            }
+        }
+
+        m_shift.insert(chrom.to_string(), cur_start);
+        if size > 0 {
+            m_names.insert(cur_start, chrom.to_string());
+        }
         load.push(Iv {
             start: cur_start,
             stop: cur_start + size,
@@ -186,79 +452,227 @@ pub fn read_genome(file: &Path, mask: &Option<MaskShift>) -> GenomeShift {
         });
         cur_start += size;
         tot_masked += masked_bases;
-    }
+    });
     info!("loaded {} chromosomes", load.len());
     info!("total genome size: {}", cur_start);
     if tot_masked != 0 {
         info!("masked {} bases", tot_masked);
     }
 
-    GenomeShift {
+    let stats = InputStats {
+        entries: load.len(),
+        loaded: load.len(),
+        bases: cur_start,
+        masked_bases: tot_masked,
+        ..m_parser.stats()
+    };
+    // empty chromosomes and masks at chromosome ends don't start a segment
+    segments.dedup();
+    segments.retain(|&s| s < cur_start);
+    let mut longest_segment: HashMap<u64, u64> = HashMap::new();
+    let ends = segments.iter().skip(1).chain(std::iter::once(&cur_start));
+    for (&start, &stop) in segments.iter().zip(ends) {
+        // empty chromosomes sort before the one sharing their start
+        let chrom = &load[load.partition_point(|c| c.start <= start) - 1];
+        let longest = longest_segment.entry(chrom.start).or_default();
+        *longest = std::cmp::max(*longest, stop - start);
+    }
+    let genome = GenomeShift {
         chrom: Lapper::new(load),
         shift: m_shift,
         span: cur_start,
         gap_budget: None,
-    }
+        blocks: None,
+        exclude: None,
+        names: m_names,
+        mask: mask.clone(),
+        segments,
+        longest_segment,
+    };
+    (genome, stats)
 }
 
-pub fn read_bed(file: &Path, genome: &GenomeShift, mask: &Option<MaskShift>) -> Lapper<u64, u64> {
+pub fn read_bed(
+    file: &Path,
+    genome: &GenomeShift,
+    mask: &Option<MaskShift>,
+    payload: Payload,
+    clip_masked: bool,
+) -> (Lapper<u64, u64>, InputStats) {
     /*
-    Read bed file into a Lapper
+    Read bed file into a Lapper with each interval's val holding its `payload`.
+    Entries overlapping the mask are dropped or, with `clip_masked`, keep their unmasked bases
     */
     info!("parsing {}", file.display());
-    let mut ret: Vec<Iv> = vec![];
+    let mut per_chrom: HashMap<Rc<str>, Vec<Iv>> = HashMap::new();
     let mut tot_size: u64 = 0;
     let mut num_masked = 0;
-    let mut warned_chroms: Vec<String> = vec![];
+    let mut num_missing = 0;
+    let mut warned_chroms: HashSet<Rc<str>> = HashSet::new();
 
     let mut m_parser = BedParser::new(file);
-    for (chrom, m_start, m_stop) in m_parser.parse(true).into_iter() {
-        if !genome.shift.contains_key(&chrom) {
+    let num_entries = m_parser.parse(true, payload, |chrom, m_start, m_stop, m_val| {
+        let Some(&r_shift) = genome.shift.get(&**chrom) else {
+            num_missing += 1;
             // only warn once
-            if !warned_chroms.contains(&chrom) {
+            if warned_chroms.insert(chrom.clone()) {
                 warn!("{} missing from --genome and won't be loaded", chrom);
-                warned_chroms.push(chrom);
             }
-            continue;
+            return;
+        };
+        let chrom_mask = mask.as_ref().and_then(|m| m.get(&**chrom));
+        if let Some(m) = chrom_mask {
+            if !clip_masked && m.find(m_start, m_stop).next().is_some() {
+                num_masked += 1;
+                return;
+            }
         }
-        let skip = match mask {
-            Some(m) => {
-                if m.contains_key(&chrom) {
-                    m[&chrom].find(m_start, m_stop).count() != 0
-                } else {
-                    false
-                }
+        // masked bases before a position
+        let l_shift = |pos: u64| -> u64 {
+            match chrom_mask {
+                Some(m) => m
+                    .find(0, pos)
+                    .map(|i| std::cmp::min(i.stop, pos) - i.start)
+                    .sum(),
+                None => 0,
             }
-            None => false,
         };
-        if skip {
+        let (l_start, l_stop) = (m_start - l_shift(m_start), m_stop - l_shift(m_stop));
+        if l_start == l_stop {
             num_masked += 1;
-            continue;
-        }
-
-        let r_shift = genome.shift[&chrom];
-        let mut l_shift = 0;
-        if mask.is_some() {
-            let t_mask = mask.as_ref().expect("MASK SOME");
-            if t_mask.contains_key(&chrom) {
-                for i in t_mask[&chrom].find(0, m_start) {
-                    l_shift += i.stop - i.start;
-                }
-            }
+            return;
         }
 
         // end-to-end chrom coordinates will r_shift (increase).
         // maksed bases before start will l_shift (decrease).
-        ret.push(Iv {
-            start: m_start + r_shift - l_shift,
-            stop: m_stop + r_shift - l_shift,
-            val: 0,
+        per_chrom.entry(chrom.clone()).or_default().push(Iv {
+            start: l_start + r_shift,
+            stop: l_stop + r_shift,
+            val: m_val,
         });
-        tot_size += m_stop - m_start;
+        tot_size += l_stop - l_start;
+    });
+
+    // chromosomes in genome order, dropping each as it's moved
+    let num_loaded = per_chrom.values().map(|v| v.len()).sum();
+    let mut chroms: Vec<(Rc<str>, Vec<Iv>)> = per_chrom.into_iter().collect();
+    chroms.sort_by_key(|(c, _)| genome.shift[&**c]);
+    let mut ret: Vec<Iv> = Vec::with_capacity(num_loaded);
+    for (_, v) in chroms.into_iter() {
+        ret.extend(v);
     }
     info!("loaded {} intervals", ret.len());
     info!("masked {} intervals", num_masked);
     info!("total span: {}", tot_size);
 
-    Lapper::new(ret)
+    let stats = InputStats {
+        entries: num_entries,
+        loaded: ret.len(),
+        missing_chrom: num_missing,
+        masked: num_masked,
+        bases: tot_size,
+        ..m_parser.stats()
+    };
+    (Lapper::new(ret), stats)
+}
+
+pub fn write_bed(
+    file: &Path,
+    intervals: &Lapper<u64, u64>,
+    genome: &GenomeShift,
+) -> io::Result<()> {
+    /*
+    Write a Lapper in concatenated genome coordinates to a bed file in reference coordinates
+    */
+    let mut out = create_output(file)?;
+    for i in intervals.iter() {
+        for (chrom, start, stop) in genome.unshift(i.start, i.stop) {
+            writeln!(out, "{}\t{}\t{}", chrom, start, stop)?;
+        }
+    }
+    out.flush()
+}
+
+/// Columns of the --summary-tsv
+pub const SUMMARY_HEADER: &str = "A\tB\tobserved\tmean\tstd_dev\tz_score\tp_val\talt\tnum_perms\trandom\tcount\tper_chrom\tA_cnt\tB_cnt\tswapped";
+
+pub fn write_summary(file: &Path, rows: &[String]) -> io::Result<()> {
+    /*
+    Write tab-delimited test summaries under a header
+    */
+    let mut out = create_output(file)?;
+    writeln!(out, "{}", SUMMARY_HEADER)?;
+    for row in rows {
+        writeln!(out, "{}", row)?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Write `contents` to a file in the temp directory unique to this process and `name`
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("regioners_{}_{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Genome of `chroms` (chrom\tsize lines) masked by `mask` (bed lines, or none if empty),
+    /// read as `read_genome` reads files. `name` keeps tests' files apart
+    pub fn test_genome(name: &str, chroms: &str, mask: &str) -> GenomeShift {
+        let mask = match mask.is_empty() {
+            true => None,
+            false => Some(read_mask(&temp_file(&format!("{}_mask.bed", name), mask)).0),
+        };
+        read_genome(&temp_file(&format!("{}_genome.txt", name), chroms), &mask).0
+    }
+
+    fn piece(chrom: &str, start: u64, stop: u64) -> (String, u64, u64) {
+        (chrom.to_string(), start, stop)
+    }
+
+    #[test]
+    fn unshift_reexpands_masks() {
+        // c1's 8..13 is masked, so concatenated c1 is 20bp and c2 starts at 20
+        let genome = test_genome("reexpand", "c1\t25\nc2\t12\n", "c1\t8\t13\n");
+        assert_eq!(genome.unshift(0, 5), [piece("c1", 0, 5)]);
+        assert_eq!(genome.unshift(10, 15), [piece("c1", 15, 20)]);
+        assert_eq!(genome.unshift(8, 20), [piece("c1", 13, 25)]);
+        assert_eq!(genome.unshift(22, 30), [piece("c2", 2, 10)]);
+    }
+
+    #[test]
+    fn unshift_splits_at_junctions() {
+        let genome = test_genome("junctions", "c1\t25\nc2\t12\n", "c1\t8\t13\nc1\t15\t16\n");
+        // across a masked region
+        assert_eq!(
+            genome.unshift(6, 10),
+            [piece("c1", 6, 8), piece("c1", 13, 15)]
+        );
+        // across two
+        assert_eq!(
+            genome.unshift(7, 12),
+            [piece("c1", 7, 8), piece("c1", 13, 15), piece("c1", 16, 18)]
+        );
+        // across chromosomes
+        assert_eq!(
+            genome.unshift(17, 21),
+            [piece("c1", 23, 25), piece("c2", 0, 2)]
+        );
+    }
+
+    #[test]
+    fn unshift_skips_empty_chromosomes() {
+        // c2 is fully masked and c3 has no bases, so c4 starts where c2 and c3 would
+        let genome = test_genome("empty", "c1\t10\nc2\t5\nc3\t0\nc4\t10\n", "c2\t0\t5\n");
+        assert_eq!(
+            genome.unshift(8, 12),
+            [piece("c1", 8, 10), piece("c4", 0, 2)]
+        );
+        assert_eq!(genome.unshift(10, 12), [piece("c4", 0, 2)]);
+        assert_eq!(genome.unshift(5, 10), [piece("c1", 5, 10)]);
+    }
 }
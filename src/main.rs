@@ -3,26 +3,37 @@ extern crate pretty_env_logger;
 #[macro_use]
 extern crate log;
 
-use std::fs::File;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::thread::JoinHandle;
 
 use clap::Parser;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use serde_json::json;
+use serde_json::{json, Value};
 
+mod checkpoint;
 mod cli;
 mod gapbreaks;
 mod io;
 mod overlappers;
+#[cfg(feature = "plot")]
 mod plot;
 mod randomizers;
+mod runner;
 mod stats;
 
-use crate::cli::ArgParser;
-use crate::io::{read_bed, read_genome, read_mask};
-use crate::randomizers::Randomizer;
+use crate::checkpoint::Checkpoint;
+#[cfg(feature = "plot")]
+use crate::cli::PlotArgs;
+use crate::cli::{ArgParser, Command, LocalzArgs, MatrixArgs, MergeArgs, ShuffleArgs, TestArgs};
+use crate::io::{
+    create_output, write_bed, write_summary, GenomeShift, MaskShift, Payload, SIGNAL_SCALE, STDIO,
+};
+use crate::overlappers::{CountMethod, Overlapper};
+use crate::randomizers::{perm_rand, stream_seed};
+use crate::runner::{
+    config_hash, load_bed, load_exclude, load_genome, permute, pick_seed, prepare, ready_genome,
+    split_groups, summary_row, Prepared, Provenance,
+};
 use crate::stats::{LocalZscore, PermTest};
 
 fn main() -> std::io::Result<()> {
@@ -30,114 +41,821 @@ fn main() -> std::io::Result<()> {
         .filter_level(log::LevelFilter::Info)
         .init();
 
+    match ArgParser::parse().command {
+        Command::Test(args) => test_main(args),
+        Command::Shuffle(args) => shuffle_main(args),
+        Command::Localz(args) => localz_main(args),
+        Command::Merge(args) => merge_main(args),
+        #[cfg(feature = "plot")]
+        Command::Plot(args) => plot_main(args),
+        Command::Matrix(args) => matrix_main(args),
+    }
+}
+
+/// Read a json written by a subcommand
+fn read_json(path: &Path) -> Value {
+    let contents = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        error!("unable to read {}: {}", path.display(), e);
+        std::process::exit(1);
+    });
+    serde_json::from_str(&contents).unwrap_or_else(|e| {
+        error!("unable to parse {}: {}", path.display(), e);
+        std::process::exit(1);
+    })
+}
+
+/// Rebuild a results' test from its observed count and permutations.
+/// Statistics that aren't finite (e.g. z_score) are written as null and can't be read back
+fn read_test(test: &Value) -> Result<PermTest, String> {
+    let observed = test["observed"].as_u64().ok_or("missing observed")?;
+    let perms: Vec<u64> =
+        serde_json::from_value(test["perms"].clone()).map_err(|e| e.to_string())?;
+    Ok(PermTest::new(observed, perms))
+}
+
+/// Write a json
+fn write_json(path: &Path, data: &Value) -> std::io::Result<()> {
+    let mut file = create_output(path)?;
+    file.write_all(serde_json::to_string(data).unwrap().as_bytes())?;
+    file.flush()
+}
+
+/// Run the permutation test
+fn test_main(mut args: TestArgs) -> std::io::Result<()> {
     // IO
-    let args = ArgParser::parse();
     if !args.validate() {
         error!("please fix arguments");
         std::process::exit(1);
     }
+    // the dumped sets are A's
+    if args.dump_perms.is_some() {
+        args.perm.no_swap = true;
+    }
 
-    let mask = args.mask.as_ref().map(|p| read_mask(p));
-    let mut genome = read_genome(&args.genome, &mask);
-    let mut a_intv = read_bed(&args.bed_a, &genome, &mask);
-    let mut b_intv = read_bed(&args.bed_b, &genome, &mask);
-
-    // Setup
-    if !args.no_merge {
-        info!("merging overlaps");
-        a_intv.merge_overlaps();
-        b_intv.merge_overlaps();
+    let (mut genome, mask, mut prov) = load_genome(&args.genome);
+    load_exclude(&args.random, &mut genome, &mask, &mut prov);
+    if args.group_by.is_some() {
+        return group_test_main(args, genome, mask, prov);
     }
+    let (a_intv, a_stats) = load_bed(
+        &args.bed_a,
+        &genome,
+        &mask,
+        args.random.no_merge,
+        args.perm.count.payload_a(),
+    );
+    let (b_intv, b_stats) = load_bed(
+        &args.bed_b,
+        &genome,
+        &mask,
+        args.random.no_merge,
+        args.perm.count.payload_b(),
+    );
+    prov.inputs = vec![a_stats, b_stats];
+    prov.threads = args.perm.num_threads();
     let a_count = a_intv.len();
     let b_count = b_intv.len();
-    let swapped = if !args.no_swap & (a_count > b_count) {
-        info!("swapping A for shorter B");
-        std::mem::swap(&mut a_intv, &mut b_intv);
-        true
-    } else {
-        false
-    };
-    if args.random == Randomizer::Novl {
-        genome.make_gap_budget(&a_intv, &args.per_chrom)
-    }
-    // Won't need to change again. Can pass pointers to threads
-    let genome = Arc::new(genome);
-    let a_intv = Arc::new(a_intv);
-    let b_intv = Arc::new(b_intv);
+
+    // Setup
+    let data = prepare(
+        genome,
+        Arc::new(a_intv),
+        Arc::new(b_intv),
+        &args.random,
+        &args.perm,
+    );
 
     // profiling
     /*let guard = pprof::ProfilerGuardBuilder::default().frequency(1000).blocklist(&["libc", "libgcc", "pthread", "vdso"]).build().unwrap();*/
 
     // Processing
-    let initial_overlap_count: u64 = args.count.ovl(&a_intv, &b_intv);
+    let initial_overlap_count: u64 =
+        args.perm
+            .count
+            .ovl(&data.a_intv, &data.b_intv, args.perm.count_method);
     info!("observed : {}", initial_overlap_count);
 
-    let (progs, pb) = {
-        let chunk_size: u32 = ((args.num_times as f32) / (args.threads as f32)).ceil() as u32;
-        let progs = MultiProgress::new();
-        let sty = ProgressStyle::with_template(
-            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
-        )
-        .unwrap()
-        .progress_chars("##-");
-
-        let mut pb: Vec<ProgressBar> = vec![];
-        for _i in 0..args.threads {
-            let p = progs.add(ProgressBar::new(chunk_size.into()));
-            p.set_style(sty.clone());
-            pb.push(p);
-        }
-        (progs, pb)
+    let seed = pick_seed(args.perm.seed);
+    let (perm_counts, seed) = match &args.checkpoint {
+        Some(path) => permute_checkpointed(
+            &args,
+            &data,
+            path,
+            config_hash(&prov, &args.random, &args.perm),
+            seed,
+        )?,
+        None => {
+            let (dump_n, dump_dir) = args.dump_perms();
+            let perms = permute(
+                &data,
+                &args.random,
+                &args.perm,
+                seed,
+                (0, args.perm.num_times),
+                dump_n,
+                &dump_dir,
+            );
+            (perms, seed)
+        }
     };
+    prov.seed = Some(seed);
+    /*if let Ok(report) = guard.report().build() { println!("report: {:?}", &report); };*/
+
+    // Calculate
+    let test = PermTest::new(initial_overlap_count, perm_counts);
+    let local_zscores = LocalZscore::new(
+        &data.a_intv,
+        &data.b_intv,
+        args.perm.count,
+        args.perm.count_method,
+        args.window,
+        args.step,
+        &test,
+    );
+
+    // Output
+    info!("perm mu: {}", test.mean);
+    info!("perm sd: {}", test.std_dev);
+    info!("alt hypo : {}", test.alt);
+    info!("p-val : {}", test.p_val);
+    if let Some(path) = &args.summary_tsv {
+        let row = summary_row(
+            &args.bed_a,
+            &args.bed_b,
+            &test,
+            &args.random,
+            args.perm.count,
+            a_count,
+            b_count,
+            data.swapped,
+        );
+        write_summary(path, &[row])?;
+    }
+    let a_bases: u64 = data.a_intv.iter().map(|i| i.stop - i.start).sum();
+    let signal = signal_json(args.perm.count, a_bases, &test);
+    prov.finish();
+    let data = json!({"test": test,
+                      "signal": signal,
+                      "group_by": args.group_by,
+                      "swapped": data.swapped,
+                      "no_merge": args.random.no_merge,
+                      "random": args.random.random,
+                      "block_size": args.random.block_size(),
+                      "count": args.perm.count,
+                      "A_cnt" : a_count,
+                      "B_cnt" : b_count,
+                      "per_chrom": args.random.per_chrom,
+                      "localZ": local_zscores,
+                      "provenance": prov,
+    });
+    if !args.no_plot {
+        plot_test(&data, &args);
+    }
 
-    let handles: Vec<JoinHandle<Vec<u64>>> = (0..args.threads)
-        .map(|i| {
-            let m_a = a_intv.clone();
-            let m_b = b_intv.clone();
-            let m_g = genome.clone();
-            let m_p = pb[i as usize].clone();
-
-            std::thread::spawn(move || {
-                ((i as u32)..args.num_times)
-                    .step_by(args.threads as usize)
-                    .map(|_| {
-                        m_p.inc(1);
-                        args.count.ovl(&args.random.ize(&m_a, &m_g, args.per_chrom), &m_b)
-                    })
-                    .collect()
-            })
+    write_json(&args.output, &data)
+}
+
+/// Mean signal per base of A observed and permuted for `--count signal`
+fn signal_json(count: Overlapper, a_bases: u64, test: &PermTest) -> Option<Value> {
+    (count == Overlapper::Signal).then(|| {
+        // randomizations keep A's bases so the mean signal per base is the sum's test rescaled
+        let per_base = |x: f64| x / SIGNAL_SCALE / a_bases as f64;
+        info!("observed mean signal : {}", per_base(test.observed as f64));
+        info!("perm mean signal : {}", per_base(test.mean));
+        json!({"scale": SIGNAL_SCALE,
+               "A_bases": a_bases,
+               "observed_mean": per_base(test.observed as f64),
+               "perm_mean": per_base(test.mean),
         })
-        .collect();
+    })
+}
+
+/// Run the permutation test within each `--group-by` category of A and pooled over them.
+/// The pooled test sums the categories' observed and permuted counts
+fn group_test_main(
+    mut args: TestArgs,
+    genome: GenomeShift,
+    mask: Option<MaskShift>,
+    mut prov: Provenance,
+) -> std::io::Result<()> {
+    // A is what's randomized within its categories
+    args.perm.no_swap = true;
+    // categories are merged separately
+    let (a_intv, a_stats) = load_bed(&args.bed_a, &genome, &mask, true, Payload::Name);
+    let names = a_stats.groups.clone();
+    let groups = split_groups(a_intv, names.len(), args.random.no_merge);
+    info!("testing {} groups", names.len());
+    let (b_intv, b_stats) = load_bed(
+        &args.bed_b,
+        &genome,
+        &mask,
+        args.random.no_merge,
+        args.perm.count.payload_b(),
+    );
+    prov.inputs = vec![a_stats, b_stats];
+    prov.threads = args.perm.num_threads();
+    let b_count = b_intv.len();
+    let b_intv = Arc::new(b_intv);
+    let seed = pick_seed(args.perm.seed);
+    prov.seed = Some(seed);
+
+    let mut rows = vec![];
+    let mut results = serde_json::Map::new();
+    let mut a_count = 0;
+    let mut a_bases = 0;
+    let mut observed = 0;
+    let mut perms = vec![0; args.perm.num_times as usize];
+    let mut local_counts: Vec<u64> = vec![];
+    for (c, (name, a_intv)) in names.iter().zip(groups).enumerate() {
+        info!("testing group {} ({} intervals)", name, a_intv.len());
+        let data = prepare(
+            genome.clone(),
+            Arc::new(a_intv),
+            b_intv.clone(),
+            &args.random,
+            &args.perm,
+        );
+        let obs = args
+            .perm
+            .count
+            .ovl(&data.a_intv, &data.b_intv, args.perm.count_method);
+        // each group draws from its own permutations' streams
+        let group_perms = permute(
+            &data,
+            &args.random,
+            &args.perm,
+            stream_seed(seed, c as u64),
+            (0, args.perm.num_times),
+            0,
+            Path::new(""),
+        );
+        let test = PermTest::new(obs, group_perms);
+        let local_zscores = LocalZscore::new(
+            &data.a_intv,
+            &data.b_intv,
+            args.perm.count,
+            args.perm.count_method,
+            args.window,
+            args.step,
+            &test,
+        );
+        info!("{} observed : {}", name, test.observed);
+        info!("{} p-val : {}", name, test.p_val);
 
-    // Collect
-    let mut perm_counts = Vec::<u64>::with_capacity(args.num_times as usize);
-    for handle in handles {
-        perm_counts.extend(handle.join().unwrap());
+        observed += test.observed;
+        for (p, g) in perms.iter_mut().zip(&test.perms) {
+            *p += g;
+        }
+        local_counts.resize(local_zscores.observed.len(), 0);
+        for (l, g) in local_counts.iter_mut().zip(&local_zscores.observed) {
+            *l += g;
+        }
+        let group_bases: u64 = data.a_intv.iter().map(|i| i.stop - i.start).sum();
+        a_count += data.a_intv.len();
+        a_bases += group_bases;
+        rows.push(summary_row(
+            &PathBuf::from(format!("{}[{}]", args.bed_a.display(), name)),
+            &args.bed_b,
+            &test,
+            &args.random,
+            args.perm.count,
+            data.a_intv.len(),
+            b_count,
+            false,
+        ));
+        results.insert(
+            name.clone(),
+            json!({"test": test,
+                   "signal": signal_json(args.perm.count, group_bases, &test),
+                   "A_cnt": data.a_intv.len(),
+                   "localZ": local_zscores,
+            }),
+        );
     }
-    progs.clear().unwrap();
-    /*if let Ok(report) = guard.report().build() { println!("report: {:?}", &report); };*/
 
     // Calculate
-    let test = PermTest::new(initial_overlap_count, perm_counts);
-    let local_zscores = LocalZscore::new(&a_intv, &b_intv, args.count, args.window, args.step, &test);
+    let test = PermTest::new(observed, perms);
+    let local_zscores = LocalZscore::from_observed(local_counts, args.window, args.step, &test);
 
     // Output
+    info!("pooled observed : {}", test.observed);
     info!("perm mu: {}", test.mean);
     info!("perm sd: {}", test.std_dev);
     info!("alt hypo : {}", test.alt);
     info!("p-val : {}", test.p_val);
+    if let Some(path) = &args.summary_tsv {
+        let row = summary_row(
+            &args.bed_a,
+            &args.bed_b,
+            &test,
+            &args.random,
+            args.perm.count,
+            a_count,
+            b_count,
+            false,
+        );
+        rows.insert(0, row);
+        write_summary(path, &rows)?;
+    }
+    let signal = signal_json(args.perm.count, a_bases, &test);
+    prov.finish();
     let data = json!({"test": test,
-                      "swapped": swapped,
-                      "no_merge": args.no_merge,
-                      "random": args.random,
-                      "count": args.count,
+                      "signal": signal,
+                      "group_by": args.group_by,
+                      "groups": results,
+                      "swapped": false,
+                      "no_merge": args.random.no_merge,
+                      "random": args.random.random,
+                      "block_size": args.random.block_size(),
+                      "count": args.perm.count,
                       "A_cnt" : a_count,
                       "B_cnt" : b_count,
-                      "per_chrom": args.per_chrom,
+                      "per_chrom": args.random.per_chrom,
                       "localZ": local_zscores,
+                      "provenance": prov,
+    });
+    if !args.no_plot {
+        plot_test(&data, &args);
+    }
+
+    write_json(&args.output, &data)
+}
+
+/// Permute in batches, saving the permutations to a checkpoint after each.
+/// A resumed run continues with the checkpoint's seed, which is returned with the permutations
+fn permute_checkpointed(
+    args: &TestArgs,
+    data: &Prepared,
+    path: &Path,
+    config: String,
+    seed: u64,
+) -> std::io::Result<(Vec<u64>, u64)> {
+    let num_times = args.perm.num_times as usize;
+    let mut ckpt = if args.resume {
+        let mut ckpt = Checkpoint::load(path)?;
+        if ckpt.config != config {
+            error!("--checkpoint was made with different inputs or parameters");
+            std::process::exit(1);
+        }
+        match (ckpt.seed, args.perm.seed) {
+            (Some(c), Some(a)) if c != a => {
+                error!("--seed {} differs from the checkpoint's seed {}", a, c);
+                std::process::exit(1);
+            }
+            (None, _) => ckpt.seed = Some(seed),
+            _ => (),
+        }
+        info!("resuming from {} permutations", ckpt.perms.len());
+        ckpt
+    } else {
+        Checkpoint::new(config, Some(seed))
+    };
+    let seed = ckpt.seed.unwrap_or(seed);
+    if ckpt.perms.len() > num_times {
+        warn!(
+            "checkpoint has more than --num-times permutations. using the first {}",
+            num_times
+        );
+        ckpt.perms.truncate(num_times);
+    }
+
+    let (dump_n, dump_dir) = args.dump_perms();
+    while ckpt.perms.len() < num_times {
+        let start = ckpt.perms.len() as u32;
+        let stop = std::cmp::min(start + args.checkpoint_every, args.perm.num_times);
+        ckpt.perms.extend(permute(
+            data,
+            &args.random,
+            &args.perm,
+            seed,
+            (start, stop),
+            dump_n,
+            &dump_dir,
+        ));
+        ckpt.save(path)?;
+        info!("checkpoint {}/{}", stop, num_times);
+    }
+    Ok((ckpt.perms, seed))
+}
+
+/// Write randomized copies of -A
+fn shuffle_main(args: ShuffleArgs) -> std::io::Result<()> {
+    if !args.validate() {
+        error!("please fix arguments");
+        std::process::exit(1);
+    }
+
+    let (mut genome, mask, mut prov) = load_genome(&args.genome);
+    load_exclude(&args.random, &mut genome, &mask, &mut prov);
+    let (a_intv, _) = load_bed(
+        &args.bed_a,
+        &genome,
+        &mask,
+        args.random.no_merge,
+        Payload::Empty,
+    );
+    ready_genome(&mut genome, &a_intv, &args.random);
+
+    let seed = pick_seed(args.seed);
+    info!("seed : {}", seed);
+    for i in 0..args.num_times {
+        let path = match args.output == Path::new(STDIO) {
+            true => args.output.clone(),
+            false => args.output.join(format!("perm_{}.bed", i)),
+        };
+        let r_intv = args.random.random.ize(
+            &a_intv,
+            &genome,
+            args.random.per_chrom,
+            &mut perm_rand(seed, i as u64),
+        );
+        write_bed(&path, &r_intv, &genome)?;
+    }
+    info!("wrote {} randomized bed files", args.num_times);
+    Ok(())
+}
+
+/// Recalculate an existing test's local z-scores
+fn localz_main(args: LocalzArgs) -> std::io::Result<()> {
+    if !args.validate() {
+        error!("please fix arguments");
+        std::process::exit(1);
+    }
+
+    let mut data = read_json(&args.results);
+    if !data["groups"].is_null() {
+        error!("results were run with --group-by. rerun `regioners test` to change its localZ");
+        std::process::exit(1);
+    }
+    let test = read_test(&data["test"]).unwrap_or_else(|e| {
+        error!("results missing test: {}", e);
+        std::process::exit(1);
+    });
+    let count: Overlapper = serde_json::from_value(data["count"].clone()).unwrap_or_else(|e| {
+        error!("results missing count: {}", e);
+        std::process::exit(1);
     });
-    let _ = plot::plot_results(&data, &args.output);
+    let no_merge = data["no_merge"].as_bool().unwrap_or(false);
+    let swapped = data["swapped"].as_bool().unwrap_or(false);
+
+    let (genome, mask, _) = load_genome(&args.genome);
+    let (mut a_intv, _) = load_bed(&args.bed_a, &genome, &mask, no_merge, count.payload_a());
+    let (mut b_intv, _) = load_bed(&args.bed_b, &genome, &mask, no_merge, count.payload_b());
+    if swapped {
+        std::mem::swap(&mut a_intv, &mut b_intv);
+    }
+
+    let observed = count.ovl(&a_intv, &b_intv, CountMethod::Auto);
+    if observed != test.observed {
+        warn!(
+            "observed intersections {} differ from results' {}. Are the inputs the same?",
+            observed, test.observed
+        );
+    }
+
+    let local_zscores = LocalZscore::new(
+        &a_intv,
+        &b_intv,
+        count,
+        CountMethod::Auto,
+        args.window,
+        args.step,
+        &test,
+    );
+    data["localZ"] = json!(local_zscores);
+    write_json(&args.output, &data)
+}
+
+/// Keys of a result that must be identical across shards
+const SHARD_KEYS: [&str; 8] = [
+    "random",
+    "block_size",
+    "group_by",
+    "count",
+    "per_chrom",
+    "no_merge",
+    "swapped",
+    "test/observed",
+];
+
+/// Combine the permutations of tests run as independent shards
+fn merge_main(args: MergeArgs) -> std::io::Result<()> {
+    if !args.validate() {
+        error!("please fix arguments");
+        std::process::exit(1);
+    }
+
+    let shards: Vec<Value> = args.results.iter().map(|p| read_json(p)).collect();
+    let first = &shards[0];
+    // inputs are compared by content
+    let md5s = |v: &Value| -> Vec<Value> {
+        let prov = &v["provenance"];
+        let mut ret = vec![
+            prov["genome"]["md5"].clone(),
+            prov["mask"]["md5"].clone(),
+            prov["exclude"]["md5"].clone(),
+        ];
+        if let Some(inputs) = prov["inputs"].as_array() {
+            ret.extend(inputs.iter().map(|i| i["md5"].clone()));
+        }
+        ret
+    };
+    let localz = |v: &Value| -> Vec<Value> {
+        ["window", "step", "observed"]
+            .iter()
+            .map(|k| v["localZ"][k].clone())
+            .collect()
+    };
+
+    let mut is_ok = true;
+    for (path, shard) in args.results.iter().zip(shards.iter()) {
+        for key in SHARD_KEYS {
+            let ptr = format!("/{}", key);
+            // results from before a key existed hold null for it
+            let get = |v: &Value| v.pointer(&ptr).cloned().unwrap_or_default();
+            if get(shard) != get(first) {
+                error!(
+                    "{} has a different {} than {}",
+                    path.display(),
+                    key,
+                    args.results[0].display()
+                );
+                is_ok = false;
+            }
+        }
+        if md5s(shard) != md5s(first) {
+            error!(
+                "{} has different inputs than {}",
+                path.display(),
+                args.results[0].display()
+            );
+            is_ok = false;
+        }
+        if localz(shard) != localz(first) {
+            error!(
+                "{} has different localZ than {}",
+                path.display(),
+                args.results[0].display()
+            );
+            is_ok = false;
+        }
+        let groups = |v: &Value| v["groups"].as_object().map(|g| g.keys().cloned().collect());
+        let names: Option<Vec<String>> = groups(shard);
+        if names != groups(first) {
+            error!(
+                "{} has different groups than {}",
+                path.display(),
+                args.results[0].display()
+            );
+            is_ok = false;
+        }
+        for name in names.iter().flatten() {
+            let (group, first_group) = (&shard["groups"][name], &first["groups"][name]);
+            if (group["test"]["observed"] != first_group["test"]["observed"])
+                | (localz(group) != localz(first_group))
+            {
+                error!(
+                    "{} has a different group {} than {}",
+                    path.display(),
+                    name,
+                    args.results[0].display()
+                );
+                is_ok = false;
+            }
+        }
+    }
+    if !is_ok {
+        error!("shards must be runs of the same test");
+        std::process::exit(1);
+    }
+    // shards run with the same seed repeat the same permutations
+    let mut seeds: Vec<u64> = shards
+        .iter()
+        .filter_map(|v| v["provenance"]["seed"].as_u64())
+        .collect();
+    seeds.sort_unstable();
+    for w in seeds.windows(2).filter(|w| w[0] == w[1]) {
+        warn!(
+            "multiple shards used seed {}. their permutations are identical",
+            w[0]
+        );
+    }
+
+    let tests: Vec<&Value> = shards.iter().collect();
+    let mut data = pool_shards(&args.results, &tests);
+    // groups are each pooled like the test over them
+    let names: Vec<String> = match first["groups"].as_object() {
+        Some(g) => g.keys().cloned().collect(),
+        None => vec![],
+    };
+    for name in names {
+        info!("merging group {}", name);
+        let tests: Vec<&Value> = shards.iter().map(|v| &v["groups"][&name]).collect();
+        data["groups"][&name] = pool_shards(&args.results, &tests);
+    }
+    data["provenance"]["merged"] = json!(args.results);
+    write_json(&args.output, &data)
+}
+
+/// Pool a test's permutations across shards. `tests` hold each shard's test with its localZ and signal.
+/// Returns the first's with the pooled test and rebuilt localZ and signal
+fn pool_shards(paths: &[PathBuf], tests: &[&Value]) -> Value {
+    let mut perms: Vec<u64> = vec![];
+    for (path, shard) in paths.iter().zip(tests) {
+        let test = read_test(&shard["test"]).unwrap_or_else(|e| {
+            error!("{} missing test: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        perms.extend(test.perms);
+    }
 
-    let mut file = File::create(args.output)?;
-    file.write_all(serde_json::to_string(&data).unwrap().as_bytes())
+    let first = tests[0];
+    let mut data = first.clone();
+    let observed = first["test"]["observed"].as_u64().unwrap_or_default();
+    info!(
+        "merging {} permutations from {} shards",
+        perms.len(),
+        tests.len()
+    );
+    let test = PermTest::new(observed, perms);
+
+    // local z-scores are rebuilt from each shift's observed count against the pooled permutations
+    let local_counts: Option<Vec<u64>> =
+        serde_json::from_value(first["localZ"]["observed"].clone()).ok();
+    match local_counts {
+        Some(counts) => {
+            let window = first["localZ"]["window"].as_i64().unwrap_or_default();
+            let step = first["localZ"]["step"].as_u64().unwrap_or(1);
+            data["localZ"] = json!(LocalZscore::from_observed(counts, window, step, &test));
+        }
+        None => {
+            warn!("shards have no localZ observed counts. Rerun `regioners localz` on the merged output");
+            data["localZ"] = Value::Null;
+        }
+    }
+    if let (Some(scale), Some(a_bases)) = (
+        data["signal"]["scale"].as_f64(),
+        data["signal"]["A_bases"].as_f64(),
+    ) {
+        data["signal"]["perm_mean"] = json!(test.mean / scale / a_bases);
+    }
+    data["test"] = json!(test);
+    data
+}
+
+/// Plot a test's results beside its output json
+#[cfg(feature = "plot")]
+fn plot_test(data: &Value, args: &TestArgs) {
+    let prefix = match (&args.plot_prefix, args.output == Path::new(STDIO)) {
+        (Some(p), _) => p.clone(),
+        (None, false) => plot::prefix_of(&args.output),
+        (None, true) => {
+            info!("output is stdout and no --plot-prefix. skipping plots");
+            return;
+        }
+    };
+    if let Err(e) = plot::plot_results(data, &prefix, &args.plot_opts()) {
+        warn!("unable to plot: {}", e);
+    }
+}
+
+#[cfg(not(feature = "plot"))]
+fn plot_test(_data: &Value, _args: &TestArgs) {
+    info!("built without plotting. skipping plots");
+}
+
+/// Plot an existing test's results
+#[cfg(feature = "plot")]
+fn plot_main(args: PlotArgs) -> std::io::Result<()> {
+    if !args.validate() {
+        error!("please fix arguments");
+        std::process::exit(1);
+    }
+
+    // a single test result or a batch of named results
+    let data = if args.results.len() == 1 {
+        read_json(&args.results[0])
+    } else {
+        let mut batch = vec![];
+        for path in &args.results {
+            match read_json(path) {
+                Value::Array(v) => batch.extend(v),
+                mut v => {
+                    if v.get("name").is_none() {
+                        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+                        v["name"] = json!(name);
+                    }
+                    batch.push(v);
+                }
+            }
+        }
+        Value::Array(batch)
+    };
+    let prefix = match &args.prefix {
+        Some(p) => p.clone(),
+        None => plot::prefix_of(&args.results[0]),
+    };
+    if let Err(e) = plot::plot_results(&data, &prefix, &args.opts(data.is_array())) {
+        error!("unable to plot: {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Permutation test every pair of inputs
+fn matrix_main(args: MatrixArgs) -> std::io::Result<()> {
+    if !args.validate() {
+        error!("please fix arguments");
+        std::process::exit(1);
+    }
+
+    let (mut genome, mask, mut prov) = load_genome(&args.genome);
+    load_exclude(&args.random, &mut genome, &mask, &mut prov);
+    // every input is both an A and a B, so it holds scores if either side counts them
+    let payload = match (args.perm.count.payload_a(), args.perm.count.payload_b()) {
+        (Payload::Score, _) | (_, Payload::Score) => Payload::Score,
+        (payload, _) => payload,
+    };
+    let (beds, stats): (Vec<_>, Vec<_>) = args
+        .input
+        .iter()
+        .map(|p| {
+            let (intv, stats) = load_bed(p, &genome, &mask, args.random.no_merge, payload);
+            (Arc::new(intv), stats)
+        })
+        .unzip();
+    prov.inputs = stats;
+    prov.threads = args.perm.num_threads();
+    let seed = pick_seed(args.perm.seed);
+    prov.seed = Some(seed);
+
+    let n = beds.len();
+    let mut observed = vec![vec![None; n]; n];
+    let mut z_score = vec![vec![None; n]; n];
+    let mut p_val = vec![vec![None; n]; n];
+    let mut rows = vec![];
+    for i in 0..n {
+        for j in 0..n {
+            if i == j {
+                continue;
+            }
+            info!(
+                "testing {} against {}",
+                args.input[i].display(),
+                args.input[j].display()
+            );
+            let data = prepare(
+                genome.clone(),
+                beds[i].clone(),
+                beds[j].clone(),
+                &args.random,
+                &args.perm,
+            );
+            let obs = args
+                .perm
+                .count
+                .ovl(&data.a_intv, &data.b_intv, args.perm.count_method);
+            let perms = permute(
+                &data,
+                &args.random,
+                &args.perm,
+                seed,
+                (0, args.perm.num_times),
+                0,
+                Path::new(""),
+            );
+            let test = PermTest::new(obs, perms);
+            rows.push(summary_row(
+                &args.input[i],
+                &args.input[j],
+                &test,
+                &args.random,
+                args.perm.count,
+                beds[i].len(),
+                beds[j].len(),
+                data.swapped,
+            ));
+            observed[i][j] = Some(test.observed);
+            z_score[i][j] = Some(test.z_score);
+            p_val[i][j] = Some(test.p_val);
+        }
+    }
+
+    prov.finish();
+    let data = json!({"names": args.input,
+                      "num_perms": args.perm.num_times,
+                      "no_merge": args.random.no_merge,
+                      "random": args.random.random,
+                      "block_size": args.random.block_size(),
+                      "count": args.perm.count,
+                      "per_chrom": args.random.per_chrom,
+                      "observed": observed,
+                      "z_score": z_score,
+                      "p_val": p_val,
+                      "provenance": prov,
+    });
+    if let Some(path) = &args.summary_tsv {
+        write_summary(path, &rows)?;
+    }
+    write_json(&args.output, &data)
 }
@@ -1,34 +1,361 @@
 //! Overlap counters
 use clap::ValueEnum;
 use rust_lapper::Lapper;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize)]
+use crate::io::{Iv, Payload};
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Overlapper {
     /// Count number of overlaps
     All,
     /// Count if any overlap
     Any,
+    /// Count overlapping base pairs
+    Bp,
+    /// Sum B's scores over overlaps
+    Score,
+    /// Sum the products of A and B's scores over overlaps
+    Product,
+    /// Sum B's bedGraph signal over A's bases
+    Signal,
+}
+
+/// How overlaps of A with B are found
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CountMethod {
+    /// pick by the sizes of A and B
+    Auto,
+    /// look up each A interval in B
+    Find,
+    /// sweep through A and B together
+    Sweep,
+}
+
+/// Sweep once B has fewer than this many intervals per A interval.
+/// The two break even between 50 and 100 on uniformly spread regions (see `tests::sweep_break_even`)
+/// and long B intervals only slow finding, so ties go to sweeping
+const SWEEP_RATIO: usize = 100;
+
+impl CountMethod {
+    /// Finding costs a search of B per A interval while sweeping walks all of B.
+    /// So sweep unless A is sparse relative to B. Sweeping also isn't slowed by long B intervals
+    fn resolve(&self, a_len: usize, b_len: usize) -> CountMethod {
+        match self {
+            CountMethod::Auto if b_len < a_len.saturating_mul(SWEEP_RATIO) => CountMethod::Sweep,
+            CountMethod::Auto => CountMethod::Find,
+            m => *m,
+        }
+    }
 }
 
 /// For each interval in A, count any or all overlaps with B
 impl Overlapper {
-    pub fn ovl(&self, a_intv: &Lapper<u64, u64>, b_intv: &Lapper<u64, u64>) -> u64 {
+    /// What the counter needs A's intervals to hold
+    pub fn payload_a(&self) -> Payload {
+        match self {
+            Overlapper::Product => Payload::Score,
+            _ => Payload::Empty,
+        }
+    }
+
+    /// What the counter needs B's intervals to hold. `signal` reads B as a bedGraph
+    pub fn payload_b(&self) -> Payload {
+        match self {
+            Overlapper::Score | Overlapper::Product => Payload::Score,
+            Overlapper::Signal => Payload::Signal,
+            _ => Payload::Empty,
+        }
+    }
+
+    /// Whether A and B can be swapped without changing what's counted
+    pub fn swappable(&self) -> bool {
+        !matches!(self, Overlapper::Score | Overlapper::Signal)
+    }
+
+    pub fn ovl(
+        &self,
+        a_intv: &Lapper<u64, u64>,
+        b_intv: &Lapper<u64, u64>,
+        method: CountMethod,
+    ) -> u64 {
+        self.ovl_sorted(
+            &a_intv.intervals,
+            &b_intv.intervals,
+            max_len(&b_intv.intervals),
+            method,
+        )
+    }
+
+    /// Count overlaps of A's intervals with B's. Both must be sorted by start
+    /// and `b_max_len` is B's longest interval
+    pub fn ovl_sorted(
+        &self,
+        a_intv: &[Iv],
+        b_intv: &[Iv],
+        b_max_len: u64,
+        method: CountMethod,
+    ) -> u64 {
+        match method.resolve(a_intv.len(), b_intv.len()) {
+            CountMethod::Sweep => self.sweep(a_intv, b_intv),
+            _ => self.find(a_intv, b_intv, b_max_len),
+        }
+    }
+
+    /// Score an A interval from the B intervals it overlaps
+    fn score<'a>(&self, i: &Iv, mut hits: impl Iterator<Item = &'a Iv>) -> u64 {
         match self {
             /* Return number of B intervals intersecting each A interval */
-            Overlapper::All => a_intv
-                .iter()
-                .map(|i| b_intv.find(i.start, i.stop).count() as u64)
-                .sum(),
+            Overlapper::All => hits.count() as u64,
             /* Return number of A intervals intersecting a B intervals */
-            Overlapper::Any => a_intv
-                .iter()
-                .map(|i| match b_intv.find(i.start, i.stop).next() {
-                    Some(_) => 1,
-                    None => 0,
-                })
+            Overlapper::Any => hits.next().is_some() as u64,
+            /* Return number of bases of each A interval covered by each B interval */
+            Overlapper::Bp => hits
+                .map(|j| std::cmp::min(i.stop, j.stop) - std::cmp::max(i.start, j.start))
                 .sum(),
+            /* Return sum of scores of B intervals intersecting each A interval */
+            Overlapper::Score => hits.map(|j| j.val).sum(),
+            /* Return sum of each A interval's score times the scores of B intervals intersecting it */
+            Overlapper::Product => hits.map(|j| i.val * j.val).sum(),
+            /* Return sum of B's signal on each base of each A interval */
+            Overlapper::Signal => hits
+                .map(|j| (std::cmp::min(i.stop, j.stop) - std::cmp::max(i.start, j.start)) * j.val)
+                .sum(),
+        }
+    }
+
+    /// Look up each A interval in B with a cursor that gallops forward,
+    /// so sparse A stay cheap against large B
+    fn find(&self, a_intv: &[Iv], b_intv: &[Iv], b_max_len: u64) -> u64 {
+        let mut cursor = 0;
+        a_intv
+            .iter()
+            .map(|i| {
+                // B intervals starting earlier end before i
+                cursor = gallop(b_intv, cursor, i.start.saturating_sub(b_max_len));
+                let hits = b_intv[cursor..]
+                    .iter()
+                    .take_while(|j| j.start < i.stop)
+                    .filter(|j| j.stop > i.start);
+                self.score(i, hits)
+            })
+            .sum()
+    }
+
+    /// Sweep through A and B together, holding the B intervals that may still overlap A.
+    /// Linear in the size of both, plus the depth of B's overlaps with each other
+    fn sweep(&self, a_intv: &[Iv], b_intv: &[Iv]) -> u64 {
+        let mut active: Vec<&Iv> = vec![];
+        let mut next_b = 0;
+        a_intv
+            .iter()
+            .map(|i| {
+                // A starts only increase, so B ending before this one can't overlap later ones
+                active.retain(|j| j.stop > i.start);
+                while next_b < b_intv.len() && b_intv[next_b].start < i.stop {
+                    if b_intv[next_b].stop > i.start {
+                        active.push(&b_intv[next_b]);
+                    }
+                    next_b += 1;
+                }
+                // A earlier, longer interval may have added B starting after this one ends
+                let hits = active.iter().copied().filter(|j| j.start < i.stop);
+                self.score(i, hits)
+            })
+            .sum()
+    }
+}
+
+/// Length of the longest interval
+pub fn max_len(intv: &[Iv]) -> u64 {
+    intv.iter().map(|i| i.stop - i.start).max().unwrap_or(0)
+}
+
+/// First index from `cursor` of `intv`, sorted by start, that starts at or after `pos`
+fn gallop(intv: &[Iv], cursor: usize, pos: u64) -> usize {
+    let (mut lo, mut step) = (cursor, 1);
+    while lo + step < intv.len() && intv[lo + step].start < pos {
+        lo += step;
+        step *= 2;
+    }
+    let hi = std::cmp::min(lo + step, intv.len());
+    lo + intv[lo..hi].partition_point(|i| i.start < pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tinyrand::{RandRange, Seeded, StdRand};
+
+    const COUNTERS: [Overlapper; 6] = [
+        Overlapper::All,
+        Overlapper::Any,
+        Overlapper::Bp,
+        Overlapper::Score,
+        Overlapper::Product,
+        Overlapper::Signal,
+    ];
+
+    /// `n` intervals sorted by start over `0..span` with lengths up to `max_len`
+    /// and every `long_every`th up to `span / 4` long
+    fn random_ivs(
+        rand: &mut StdRand,
+        n: usize,
+        span: u64,
+        max_len: u64,
+        long_every: usize,
+    ) -> Vec<Iv> {
+        let mut ret: Vec<Iv> = (0..n)
+            .map(|k| {
+                let start = rand.next_range(0..span);
+                let len = match long_every > 0 && k % long_every == 0 {
+                    true => rand.next_range(1..span / 4),
+                    false => rand.next_range(1..max_len + 1),
+                };
+                Iv {
+                    start,
+                    stop: start + len,
+                    val: rand.next_range(0..100),
+                }
+            })
+            .collect();
+        ret.sort_unstable_by_key(|i| i.start);
+        ret
+    }
+
+    /// Counts the way they were made before `find` and `sweep`: a Lapper::find of each A interval
+    fn lapper_count(count: Overlapper, a_intv: &[Iv], b_intv: &[Iv]) -> u64 {
+        let b_intv = Lapper::new(b_intv.to_vec());
+        a_intv
+            .iter()
+            .map(|i| {
+                let mut hits = b_intv.find(i.start, i.stop);
+                let bp = |j: &Iv| std::cmp::min(i.stop, j.stop) - std::cmp::max(i.start, j.start);
+                match count {
+                    Overlapper::All => hits.count() as u64,
+                    Overlapper::Any => hits.next().is_some() as u64,
+                    Overlapper::Bp => hits.map(bp).sum(),
+                    Overlapper::Score => hits.map(|j| j.val).sum(),
+                    Overlapper::Product => hits.map(|j| i.val * j.val).sum(),
+                    Overlapper::Signal => hits.map(|j| bp(j) * j.val).sum(),
+                }
+            })
+            .sum()
+    }
+
+    fn assert_counts_agree(a_intv: &[Iv], b_intv: &[Iv]) {
+        let b_max_len = max_len(b_intv);
+        for count in COUNTERS {
+            let expected = lapper_count(count, a_intv, b_intv);
+            assert_eq!(count.find(a_intv, b_intv, b_max_len), expected);
+            assert_eq!(count.sweep(a_intv, b_intv), expected);
+        }
+    }
+
+    #[test]
+    fn methods_agree_with_lapper() {
+        let mut rand = StdRand::seed(26);
+        // (A count, B count, span, longest length, every Nth B interval is long)
+        let cases = [
+            // unmerged, heavily overlapping
+            (200, 2_000, 10_000, 500, 0),
+            // sparse A against dense B and the reverse
+            (10, 5_000, 100_000, 100, 0),
+            (5_000, 10, 100_000, 100, 0),
+            // long B intervals spanning many others
+            (500, 1_000, 100_000, 50, 50),
+            (500, 1_000, 100_000, 50, 3),
+        ];
+        for (n_a, n_b, span, len, long_every) in cases {
+            let a_intv = random_ivs(&mut rand, n_a, span, len, 0);
+            let b_intv = random_ivs(&mut rand, n_b, span, len, long_every);
+            assert_counts_agree(&a_intv, &b_intv);
+            assert_counts_agree(&b_intv, &a_intv);
+        }
+    }
+
+    #[test]
+    fn methods_agree_on_nested() {
+        let iv = |start, stop, val| Iv { start, stop, val };
+        // intervals inside intervals, sharing starts and stops, and touching without overlapping
+        let b_intv = vec![
+            iv(0, 1_000, 1),
+            iv(0, 10, 2),
+            iv(5, 500, 3),
+            iv(10, 20, 4),
+            iv(20, 30, 5),
+            iv(100, 1_000, 6),
+            iv(990, 1_000, 7),
+            iv(1_000, 1_001, 8),
+        ];
+        let a_intv = vec![
+            iv(0, 5, 1),
+            iv(0, 2_000, 2),
+            iv(10, 20, 3),
+            iv(15, 16, 4),
+            iv(30, 100, 5),
+            iv(999, 1_000, 6),
+            iv(1_001, 1_002, 7),
+        ];
+        assert_counts_agree(&a_intv, &b_intv);
+        assert_counts_agree(&b_intv, &a_intv);
+        assert_counts_agree(&a_intv, &[]);
+        assert_counts_agree(&[], &b_intv);
+    }
+
+    #[test]
+    fn gallop_finds_first_at_or_after() {
+        // runs of repeated starts straddle each of gallop's doubling steps
+        let starts = [
+            0, 0, 1, 2, 2, 2, 3, 5, 8, 8, 8, 8, 8, 13, 21, 21, 34, 55, 55, 89,
+        ];
+        let intv: Vec<Iv> = starts
+            .iter()
+            .map(|&s| Iv {
+                start: s,
+                stop: s + 1,
+                val: 0,
+            })
+            .collect();
+        for cursor in 0..=intv.len() {
+            for pos in 0..=90 {
+                let expected = cursor + intv[cursor..].partition_point(|i| i.start < pos);
+                assert_eq!(
+                    gallop(&intv, cursor, pos),
+                    expected,
+                    "cursor {} pos {}",
+                    cursor,
+                    pos
+                );
+            }
+        }
+        assert_eq!(gallop(&[], 0, 10), 0);
+    }
+
+    /// Times `find` and `sweep` over B/A size ratios around SWEEP_RATIO on uniformly spread regions.
+    /// `cargo test --release -- --ignored --nocapture sweep_break_even`
+    #[test]
+    #[ignore]
+    fn sweep_break_even() {
+        let mut rand = StdRand::seed(1);
+        let span = 3_000_000_000;
+        let b_intv = random_ivs(&mut rand, 1_000_000, span, 50, 0);
+        let b_max_len = max_len(&b_intv);
+        println!("ratio\tfind_ms\tsweep_ms");
+        for ratio in [25, 50, 75, 100, 150, 200, 400, 800] {
+            let a_intv = random_ivs(&mut rand, b_intv.len() / ratio, span, 500, 0);
+            let time = |f: &dyn Fn() -> u64| {
+                let now = std::time::Instant::now();
+                for _ in 0..10 {
+                    std::hint::black_box(f());
+                }
+                now.elapsed().as_secs_f64() * 100.0
+            };
+            let find = time(&|| Overlapper::All.find(&a_intv, &b_intv, b_max_len));
+            let sweep = time(&|| Overlapper::All.sweep(&a_intv, &b_intv));
+            println!("{}\t{:.2}\t{:.2}", ratio, find, sweep);
         }
     }
 }
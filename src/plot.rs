@@ -1,16 +1,77 @@
+use clap::ValueEnum;
+use plotters::coord::Shift;
 use plotters::prelude::*;
 use serde_json::Value;
 use std::error::Error;
 use std::f64::consts::PI;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-const W: u32 = 1800;
-const H: u32 = 900;
+pub const W: u32 = 1800;
+pub const H: u32 = 900;
 const FONT: &str = "sans-serif";
 const FONT_TICK: u32 = 22;
 const FONT_LABEL: u32 = 26;
 const FONT_TITLE: u32 = 30;
 
+/// Plots that can be drawn from a test's results
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Panel {
+    /// permutation distribution
+    Perms,
+    /// local z-scores
+    Localz,
+    /// permutation distribution, local z-scores and test statistics side by side
+    Summary,
+    /// log2 fold enrichment with permutation CI per test (batch results)
+    Forest,
+    /// log2 fold enrichment vs -log10 adjusted p-value (batch results)
+    Volcano,
+}
+
+/// Plot file formats
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum PlotFormat {
+    /// bitmap
+    Png,
+    /// vector graphics
+    Svg,
+    /// vector graphics (requires building with `--features pdf`)
+    #[cfg(feature = "pdf")]
+    Pdf,
+}
+
+impl PlotFormat {
+    fn ext(&self) -> &'static str {
+        match self {
+            PlotFormat::Png => "png",
+            PlotFormat::Svg => "svg",
+            #[cfg(feature = "pdf")]
+            PlotFormat::Pdf => "pdf",
+        }
+    }
+}
+
+/// How and what to draw
+pub struct PlotOpts {
+    pub width: u32,
+    pub height: u32,
+    pub title: Option<String>,
+    pub panels: Vec<Panel>,
+    pub format: PlotFormat,
+}
+
+impl Default for PlotOpts {
+    fn default() -> Self {
+        PlotOpts {
+            width: W,
+            height: H,
+            title: None,
+            panels: vec![Panel::Perms, Panel::Localz, Panel::Summary],
+            format: PlotFormat::Png,
+        }
+    }
+}
+
 // ── KDE helpers ────────────────────────────────────────────────────────────
 
 fn scotts_bandwidth(data: &[f64]) -> f64 {
@@ -47,20 +108,187 @@ fn density_bins(data: &[f64], n_bins: usize) -> Vec<(f64, f64, f64)> {
         .collect()
 }
 
+// ── Batch helpers ──────────────────────────────────────────────────────────
+
+/// One test of a batch reduced to what the forest and volcano plots need.
+struct BatchTest {
+    name: String,
+    p_val: f64,
+    /// log2 fold enrichment of observed over the permutation mean
+    lfe: f64,
+    /// 95% permutation interval of `lfe`
+    ci: (f64, f64),
+}
+
+/// log2 fold enrichment with a pseudo-count so zero counts stay finite.
+fn log2_fe(observed: f64, expected: f64) -> f64 {
+    ((observed + 1.0) / (expected + 1.0)).log2()
+}
+
+/// Nearest-rank quantile of sorted data.
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    sorted[((sorted.len() - 1) as f64 * q).round() as usize]
+}
+
+/// Benjamini-Hochberg adjusted p-values, returned in input order.
+fn bh_adjust(p_vals: &[f64]) -> Vec<f64> {
+    let n = p_vals.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|a, b| p_vals[*b].total_cmp(&p_vals[*a]));
+    let mut ret = vec![0.0; n];
+    let mut running = 1.0_f64;
+    for (i, idx) in order.iter().enumerate() {
+        let rank = (n - i) as f64;
+        running = running.min(p_vals[*idx] * n as f64 / rank);
+        ret[*idx] = running;
+    }
+    ret
+}
+
+fn batch_tests(data: &Value) -> Result<Vec<BatchTest>, Box<dyn Error>> {
+    let results = data.as_array().ok_or("forest and volcano plots need batch results")?;
+    results
+        .iter()
+        .enumerate()
+        .map(|(i, r)| {
+            let test = &r["test"];
+            let obs  = test["observed"].as_f64().ok_or("missing 'observed'")?;
+            let mean = test["mean"].as_f64().ok_or("missing 'mean'")?;
+            let mut perms: Vec<f64> = test["perms"]
+                .as_array().ok_or("missing 'perms'")?
+                .iter().filter_map(|v| v.as_f64()).collect();
+            perms.sort_by(f64::total_cmp);
+            let ci = match perms.is_empty() {
+                true  => (f64::NAN, f64::NAN),
+                false => (log2_fe(obs, quantile(&perms, 0.975)), log2_fe(obs, quantile(&perms, 0.025))),
+            };
+            Ok(BatchTest {
+                name: r["name"].as_str().map_or(format!("test {i}"), |n| n.to_string()),
+                p_val: test["p_val"].as_f64().ok_or("missing 'p_val'")?,
+                lfe: log2_fe(obs, mean),
+                ci,
+            })
+        })
+        .collect()
+}
+
+/// Min/max of values padded by 5% of their range.
+fn padded_range(vals: impl Iterator<Item = f64>) -> (f64, f64) {
+    let (lo, hi) = vals
+        .filter(|x| x.is_finite())
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(l, h), x| (l.min(x), h.max(x)));
+    if !lo.is_finite() {
+        return (-1.0, 1.0);
+    }
+    let pad = ((hi - lo) * 0.05).max(0.1);
+    (lo - pad, hi + pad)
+}
+
 // ── Public entry point ──────────────────────────────────────────────────────
 
-/// Derives SVG paths from the JSON output path and writes both plots.
-pub fn plot_results(data: &Value, json_path: &Path) -> Result<(), Box<dyn Error>> {
+/// Derives the plot path prefix from the JSON output path (`dir/stem`).
+pub fn prefix_of(json_path: &Path) -> PathBuf {
     let stem = json_path.file_stem().unwrap().to_string_lossy();
     let dir  = json_path.parent().unwrap_or(Path::new("."));
-    plot_perm_dist(data, &dir.join(format!("{stem}_perms.png")))?;
-    plot_local_z(data,   &dir.join(format!("{stem}_localz.png")))?;
+    dir.join(stem.as_ref())
+}
+
+/// Writes each requested panel to `{prefix}_{panel}.{format}`.
+pub fn plot_results(data: &Value, prefix: &Path, opts: &PlotOpts) -> Result<(), Box<dyn Error>> {
+    let prefix = prefix.to_string_lossy();
+    let ext    = opts.format.ext();
+    for panel in &opts.panels {
+        let path = match panel {
+            Panel::Perms   => format!("{prefix}_perms.{ext}"),
+            Panel::Localz  => format!("{prefix}_localz.{ext}"),
+            Panel::Summary => format!("{prefix}_summary.{ext}"),
+            Panel::Forest  => format!("{prefix}_forest.{ext}"),
+            Panel::Volcano => format!("{prefix}_volcano.{ext}"),
+        };
+        render(data, *panel, Path::new(&path), opts)?;
+        info!("{} saved", path);
+    }
+    Ok(())
+}
+
+// ── Backends ────────────────────────────────────────────────────────────────
+
+/// Draws one panel with the backend matching `opts.format`.
+/// The summary holds two plots so is drawn twice as wide.
+fn render(data: &Value, panel: Panel, path: &Path, opts: &PlotOpts) -> Result<(), Box<dyn Error>> {
+    let size = match panel {
+        Panel::Summary => (opts.width * 2, opts.height),
+        _ => (opts.width, opts.height),
+    };
+    match opts.format {
+        PlotFormat::Png => draw(&BitMapBackend::new(path, size).into_drawing_area(), data, panel, opts),
+        PlotFormat::Svg => draw(&SVGBackend::new(path, size).into_drawing_area(), data, panel, opts),
+        #[cfg(feature = "pdf")]
+        PlotFormat::Pdf => {
+            let mut svg = String::new();
+            draw(&SVGBackend::with_string(&mut svg, size).into_drawing_area(), data, panel, opts)?;
+            write_pdf(&svg, path)
+        }
+    }
+}
+
+fn draw<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    data: &Value,
+    panel: Panel,
+    opts: &PlotOpts,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    match panel {
+        Panel::Perms   => plot_perm_dist(root, data, opts)?,
+        Panel::Localz  => plot_local_z(root, data, opts)?,
+        Panel::Summary => plot_summary(root, data, opts)?,
+        Panel::Forest  => plot_forest(root, data, opts)?,
+        Panel::Volcano => plot_volcano(root, data, opts)?,
+    }
+    root.present()?;
+    Ok(())
+}
+
+/// Converts an SVG document to PDF. Text is drawn with the system's fonts.
+#[cfg(feature = "pdf")]
+fn write_pdf(svg: &str, path: &Path) -> Result<(), Box<dyn Error>> {
+    use svg2pdf::{usvg, ConversionOptions, PageOptions};
+
+    let mut options = usvg::Options::default();
+    let db = options.fontdb_mut();
+    db.load_system_fonts();
+    // The generic sans-serif family defaults to Arial. Fall back to any installed sans face.
+    let query = usvg::fontdb::Query { families: &[usvg::fontdb::Family::SansSerif], ..Default::default() };
+    if db.query(&query).is_none() {
+        let family = db.faces()
+            .flat_map(|f| f.families.iter().map(|(name, _)| name))
+            .find(|name| name.contains("Sans"))
+            .cloned();
+        if let Some(family) = family {
+            db.set_sans_serif_family(family);
+        }
+    }
+    let tree = usvg::Tree::from_str(svg, &options)?;
+    let pdf  = svg2pdf::to_pdf(&tree, ConversionOptions::default(), PageOptions::default())
+        .map_err(|e| format!("unable to convert plot to pdf: {e}"))?;
+    std::fs::write(path, pdf)?;
     Ok(())
 }
 
 // ── Plot 1: permutation distribution ───────────────────────────────────────
 
-fn plot_perm_dist(data: &Value, path: &Path) -> Result<(), Box<dyn Error>> {
+fn plot_perm_dist<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    data: &Value,
+    opts: &PlotOpts,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
     let test = &data["test"];
     let perms: Vec<f64> = test["perms"]
         .as_array().ok_or("missing 'perms'")?
@@ -88,11 +316,11 @@ fn plot_perm_dist(data: &Value, path: &Path) -> Result<(), Box<dyn Error>> {
         .fold(0.0_f64, f64::max)
         * 1.15;
 
-    let root = BitMapBackend::new(path, (W, H)).into_drawing_area();
-
-    root.fill(&WHITE)?;
-
-    let mut chart = ChartBuilder::on(&root)
+    let mut builder = ChartBuilder::on(root);
+    if let Some(title) = &opts.title {
+        builder.caption(title, (FONT, FONT_TITLE));
+    }
+    let mut chart = builder
         .margin(30)
         .x_label_area_size(70)
         .y_label_area_size(90)
@@ -136,7 +364,7 @@ fn plot_perm_dist(data: &Value, path: &Path) -> Result<(), Box<dyn Error>> {
     // Plotters has no text-measure API, so estimate pixel width from char count.
     // Sans-serif characters are roughly 0.55× as wide as they are tall.
     // Then convert pixels → data units using the known axis/canvas dimensions.
-    let plot_h_px = (H - 2 * 70 - 90) as f64; // H - 2*margin - x_label_area_size
+    let plot_h_px = (root.dim_in_pixel().1 - 2 * 70 - 90) as f64; // H - 2*margin - x_label_area_size
     let text_px   = label.len() as f64 * FONT_TICK as f64 * 0.55;
     let text_span = text_px * (y_max / plot_h_px);
 
@@ -148,15 +376,19 @@ fn plot_perm_dist(data: &Value, path: &Path) -> Result<(), Box<dyn Error>> {
             .transform(FontTransform::Rotate90)
             .color(&BLACK),
     )))?;
-
-    root.present()?;
-    info!("Intersection plot saved");
     Ok(())
 }
 
 // ── Plot 2: local z-scores ─────────────────────────────────────────────────
 
-fn plot_local_z(data: &Value, path: &Path) -> Result<(), Box<dyn Error>> {
+fn plot_local_z<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    data: &Value,
+    opts: &PlotOpts,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
     let lz     = &data["localZ"];
     let window = lz["window"].as_i64().ok_or("missing 'window'")? as i32;
     let step   = lz["step"].as_i64().ok_or("missing 'step'")? as i32;
@@ -175,12 +407,9 @@ fn plot_local_z(data: &Value, path: &Path) -> Result<(), Box<dyn Error>> {
     let y_hi  = shifts[..n].iter().cloned().fold(f64::NEG_INFINITY, f64::max);
     let y_pad = (y_hi - y_lo) * 0.1;
 
-    let root = BitMapBackend::new(path, (W, H)).into_drawing_area();
-
-    root.fill(&WHITE)?;
-
-    let mut chart = ChartBuilder::on(&root)
-        .caption("Local z-score values", ("sans-serif", 22))
+    let title = opts.title.as_deref().unwrap_or("Local z-score values");
+    let mut chart = ChartBuilder::on(root)
+        .caption(title, (FONT, FONT_TITLE))
         .margin(30)
         .x_label_area_size(70)
         .y_label_area_size(90)
@@ -194,8 +423,155 @@ fn plot_local_z(data: &Value, path: &Path) -> Result<(), Box<dyn Error>> {
         .draw()?;
 
     chart.draw_series(LineSeries::new(points, &BLUE))?;
+    Ok(())
+}
 
-    root.present()?;
-    info!("Local Z plot saved");
+// ── Plot 3: summary ────────────────────────────────────────────────────────
+
+fn plot_summary<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    data: &Value,
+    opts: &PlotOpts,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    let root = match &opts.title {
+        Some(title) => root.titled(title, (FONT, FONT_TITLE))?,
+        None => root.clone(),
+    };
+    // the title is drawn once over the whole figure
+    let sub_opts = PlotOpts { title: None, panels: vec![], ..*opts };
+
+    let w = root.dim_in_pixel().0 as i32;
+    let areas = root.split_by_breakpoints([w * 2 / 5, w * 4 / 5], [] as [i32; 0]);
+    plot_perm_dist(&areas[0], data, &sub_opts)?;
+    plot_local_z(&areas[1], data, &sub_opts)?;
+
+    let test = &data["test"];
+    let num  = |v: &Value| v.as_f64().map_or("NA".to_string(), |x| format!("{x:.4}"));
+    let text = |v: &Value| match v {
+        Value::String(s) => s.clone(),
+        Value::Null => "NA".to_string(),
+        _ => v.to_string(),
+    };
+    let lines = [
+        format!("p-value: {}", num(&test["p_val"])),
+        format!("z-score: {}", num(&test["z_score"])),
+        format!("alt: {}", text(&test["alt"])),
+        format!("observed: {}", text(&test["observed"])),
+        format!("perm mean: {}", num(&test["mean"])),
+        format!("perm sd: {}", num(&test["std_dev"])),
+        format!("n perms: {}", text(&test["num_perms"])),
+        format!("randomizer: {}", text(&data["random"])),
+        format!("per-chrom: {}", text(&data["per_chrom"])),
+        format!("evaluator: {}", text(&data["count"])),
+        format!("swapped: {}", text(&data["swapped"])),
+    ];
+    let style = (FONT, FONT_LABEL).into_font().color(&BLACK);
+    for (i, line) in lines.iter().enumerate() {
+        areas[2].draw(&Text::new(line.as_str(), (20, 60 + i as i32 * FONT_LABEL as i32 * 3 / 2), style.clone()))?;
+    }
+    Ok(())
+}
+
+// ── Plot 4: forest ─────────────────────────────────────────────────────────
+
+fn plot_forest<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    data: &Value,
+    opts: &PlotOpts,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    let mut tests = batch_tests(data)?;
+    // most significant at the top
+    tests.sort_by(|a, b| b.p_val.total_cmp(&a.p_val));
+    let n = tests.len() as i32;
+
+    let (x_lo, x_hi) = padded_range(tests.iter().flat_map(|t| [t.lfe, t.ci.0, t.ci.1, 0.0]));
+    let max_name = tests.iter().map(|t| t.name.len()).max().unwrap_or(0) as u32;
+
+    let mut builder = ChartBuilder::on(root);
+    if let Some(title) = &opts.title {
+        builder.caption(title, (FONT, FONT_TITLE));
+    }
+    let mut chart = builder
+        .margin(30)
+        .x_label_area_size(70)
+        .y_label_area_size(max_name * FONT_TICK * 3 / 5 + 20)
+        .build_cartesian_2d(x_lo..x_hi, -1..n)?;
+
+    chart.configure_mesh()
+        .disable_y_mesh()
+        .x_desc("log2 Fold Enrichment")
+        .y_labels(tests.len() + 2)
+        .y_label_formatter(&|i| match usize::try_from(*i) {
+            Ok(i) if i < tests.len() => tests[i].name.clone(),
+            _ => String::new(),
+        })
+        .axis_desc_style((FONT, FONT_LABEL).into_font())
+        .label_style((FONT, FONT_TICK).into_font())
+        .draw()?;
+
+    chart.draw_series(LineSeries::new([(0.0, -1), (0.0, n)], ShapeStyle::from(&BLACK).stroke_width(1)))?;
+    chart.draw_series(tests.iter().enumerate().filter(|(_, t)| t.ci.0.is_finite()).map(|(i, t)| {
+        PathElement::new([(t.ci.0, i as i32), (t.ci.1, i as i32)], ShapeStyle::from(&BLACK).stroke_width(2))
+    }))?;
+    chart.draw_series(tests.iter().enumerate().map(|(i, t)| {
+        Circle::new((t.lfe, i as i32), 6, ShapeStyle::from(&BLUE).filled())
+    }))?;
+    Ok(())
+}
+
+// ── Plot 5: volcano ────────────────────────────────────────────────────────
+
+fn plot_volcano<DB: DrawingBackend>(
+    root: &DrawingArea<DB, Shift>,
+    data: &Value,
+    opts: &PlotOpts,
+) -> Result<(), Box<dyn Error>>
+where
+    DB::ErrorType: 'static,
+{
+    const ALPHA: f64 = 0.05;
+    let tests = batch_tests(data)?;
+    let q_vals = bh_adjust(&tests.iter().map(|t| t.p_val).collect::<Vec<_>>());
+    let points: Vec<(f64, f64)> = tests.iter().zip(&q_vals).map(|(t, q)| (t.lfe, -q.log10())).collect();
+
+    let (x_lo, x_hi) = padded_range(points.iter().map(|p| p.0).chain([0.0]));
+    let (_, y_hi)    = padded_range(points.iter().map(|p| p.1).chain([0.0, -ALPHA.log10()]));
+
+    let mut builder = ChartBuilder::on(root);
+    if let Some(title) = &opts.title {
+        builder.caption(title, (FONT, FONT_TITLE));
+    }
+    let mut chart = builder
+        .margin(30)
+        .x_label_area_size(70)
+        .y_label_area_size(90)
+        .build_cartesian_2d(x_lo..x_hi, 0.0..y_hi)?;
+
+    chart.configure_mesh()
+        .x_desc("log2 Fold Enrichment")
+        .y_desc("-log10 adjusted p-value")
+        .axis_desc_style((FONT, FONT_LABEL).into_font())
+        .label_style((FONT, FONT_TICK).into_font())
+        .draw()?;
+
+    // significance threshold
+    chart.draw_series(LineSeries::new(
+        [(x_lo, -ALPHA.log10()), (x_hi, -ALPHA.log10())],
+        ShapeStyle::from(&RED).stroke_width(1),
+    ))?;
+    chart.draw_series(points.iter().zip(&q_vals).map(|(p, q)| {
+        let color = if *q < ALPHA { RED } else { RGBColor(120, 120, 120) };
+        Circle::new(*p, 6, ShapeStyle::from(&color).filled())
+    }))?;
+    // name the significant tests
+    chart.draw_series(points.iter().zip(&q_vals).zip(&tests).filter(|((_, q), _)| **q < ALPHA).map(|((p, _), t)| {
+        Text::new(t.name.clone(), *p, (FONT, FONT_TICK).into_font().color(&BLACK))
+    }))?;
     Ok(())
 }
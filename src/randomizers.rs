@@ -1,23 +1,47 @@
 //! Interval randomization techniques
 use clap::ValueEnum;
 use rust_lapper::Lapper;
-use serde::Serialize;
-use tinyrand::{Rand, RandRange, Seeded, StdRand};
-use tinyrand_std::clock_seed::ClockSeed;
+use serde::{Deserialize, Serialize};
+use tinyrand::{Rand, RandRange, Seeded, SplitMix, StdRand};
 
 use crate::gapbreaks::GapBreaks;
 use crate::io::{GenomeShift, Iv};
 
 /// Holds the interval randomizations
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize)]
-#[serde(rename_all = "lowercase")]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum Randomizer {
     /// shuffle intervals allowing overlaps
     Shuffle,
     /// rotate intervals preserving order/spacing
     Circle,
+    /// rotate like circle but move intervals crossing an end or masked region to the next stretch they fit in instead of splitting them
+    CircleWhole,
     /// shuffle intervals without allowing overlaps
     Novl,
+    /// permute blocks of the genome with their intervals, preserving clustering within blocks
+    Block,
+}
+
+/// Buffers reused between a worker's randomizations
+#[derive(Default)]
+pub struct Scratch {
+    /// randomized intervals. Unsorted
+    pub intv: Vec<Iv>,
+    /// novl's shuffled (length, val) intervals
+    pieces: Vec<(u64, u64)>,
+    /// novl's gap sampler
+    gaps: GapBreaks,
+    /// novl's stretches between `--exclude`d regions. val is the stretch's unassigned bases
+    stretches: Vec<Iv>,
+    /// novl's total length of the stretches up to and including each
+    cum_len: Vec<u64>,
+    /// novl's (stretch, length, val) of each interval
+    assigned: Vec<(usize, u64, u64)>,
+    /// block's new position of each block
+    order: Vec<usize>,
+    /// intervals circle split or circle-whole moved in the last randomization
+    pub wrapped: u64,
 }
 
 impl Randomizer {
@@ -26,46 +50,110 @@ impl Randomizer {
         intv: &Lapper<u64, u64>,
         genome: &GenomeShift,
         per_chrom: bool,
+        rand: &mut StdRand,
     ) -> Lapper<u64, u64> {
-        Lapper::<u64, u64>::new((match self {
+        let mut scratch = Scratch::default();
+        self.ize_into(intv, genome, per_chrom, rand, &mut scratch);
+        Lapper::<u64, u64>::new(scratch.intv)
+    }
+
+    /// Randomize into `scratch.intv` without allocating once its buffers have grown
+    pub fn ize_into(
+        &self,
+        intv: &Lapper<u64, u64>,
+        genome: &GenomeShift,
+        per_chrom: bool,
+        rand: &mut StdRand,
+        scratch: &mut Scratch,
+    ) {
+        scratch.intv.clear();
+        (match self {
             Randomizer::Circle => circle_intervals,
+            Randomizer::CircleWhole => circle_whole_intervals,
             Randomizer::Shuffle => shuffle_intervals,
             Randomizer::Novl => match genome.gap_budget {
                 Some(_) => novl_intervals,
                 None => panic!("Cannot run novl randomizer without gap_budget in genome"),
             },
-        })(intv, genome, per_chrom))
+            Randomizer::Block => match genome.blocks {
+                Some(_) => block_intervals,
+                None => panic!("Cannot run block randomizer without blocks in genome"),
+            },
+        })(intv, genome, per_chrom, rand, scratch)
     }
 }
 
-/// Randomly move each interval to a new position
-fn shuffle_intervals(intv: &Lapper<u64, u64>, genome: &GenomeShift, per_chrom: bool) -> Vec<Iv> {
-    let mut rand = StdRand::seed(ClockSeed::default().next_u64());
-    intv.iter()
-        .map(|i| {
-            let (lower, upper) = if per_chrom {
-                match genome.chrom.find(i.start, i.stop).next() {
-                    Some(b) => (b.start, b.stop),
-                    None => panic!("Interval @ ({}, {}) not hitting genome", i.start, i.stop),
-                }
-            } else {
-                (0, genome.span)
-            };
-            let new_position = rand.next_range(lower..(upper - (i.stop - i.start)));
-            Iv {
-                start: new_position,
-                stop: new_position + (i.stop - i.start),
-                val: 0,
-            }
-        })
-        .collect()
+/// Random placements tried for an interval before giving up on avoiding `--exclude`
+const MAX_TRIES: u32 = 10_000;
+
+/// Seed of stream `j` of a run seeded with `seed`.
+/// `seed` is hashed before `j` is mixed in so that streams of adjacent seeds don't overlap
+pub fn stream_seed(seed: u64, j: u64) -> u64 {
+    SplitMix::seed(SplitMix::seed(seed).next_u64() ^ j).next_u64()
+}
+
+/// Random number generator of permutation `j` of a run seeded with `seed`.
+/// Each permutation gets its own stream so results don't depend on which thread runs it
+pub fn perm_rand(seed: u64, j: u64) -> StdRand {
+    StdRand::seed(stream_seed(seed, j))
 }
 
-/// Randomly shift all intervals downstream with wrap-around
-fn circle_intervals(intv: &Lapper<u64, u64>, genome: &GenomeShift, per_chrom: bool) -> Vec<Iv> {
-    let mut rand = StdRand::seed(ClockSeed::default().next_u64());
-    let mut ret: Vec<Iv> = Vec::<Iv>::with_capacity(intv.len());
+/// Randomly move each interval to a new position inside a single chromosome and stretch between masked regions.
+/// Intervals longer than every chromosome (see [`crate::runner::ready_genome`]) are placed anywhere
+fn shuffle_intervals(
+    intv: &Lapper<u64, u64>,
+    genome: &GenomeShift,
+    per_chrom: bool,
+    rand: &mut StdRand,
+    scratch: &mut Scratch,
+) {
+    let genome_longest = genome.longest_segment(None);
+    scratch.intv.extend(intv.iter().map(|i| {
+        let (lower, upper, longest) = if per_chrom {
+            match genome.chrom.find(i.start, i.stop).next() {
+                Some(b) => (b.start, b.stop, genome.longest_segment(Some(b.start))),
+                None => panic!("Interval @ ({}, {}) not hitting genome", i.start, i.stop),
+            }
+        } else {
+            (0, genome.span, genome_longest)
+        };
+        let len = i.stop - i.start;
+        // placements are redrawn until they're inside one segment and off of every excluded region.
+        // Uniform draws kept only where the interval fits weight each chromosome by its room
+        let new_position = (0..MAX_TRIES)
+            .map(|_| rand.next_range(lower..(upper - len)))
+            .find(|&p| {
+                ((len > longest) || genome.next_segment(p, p + len).is_none())
+                    && genome
+                        .exclude
+                        .as_ref()
+                        .is_none_or(|e| e.find(p, p + len).next().is_none())
+            })
+            .unwrap_or_else(|| {
+                error!(
+                    "unable to place a {}bp interval inside a chromosome and outside of --exclude in {} tries",
+                    len, MAX_TRIES
+                );
+                std::process::exit(1);
+            });
+        Iv {
+            start: new_position,
+            stop: new_position + len,
+            val: i.val,
+        }
+    }))
+}
 
+/// Randomly shift all intervals downstream with wrap-around, splitting intervals wrapped past the end
+fn circle_intervals(
+    intv: &Lapper<u64, u64>,
+    genome: &GenomeShift,
+    per_chrom: bool,
+    rand: &mut StdRand,
+    scratch: &mut Scratch,
+) {
+    let ret = &mut scratch.intv;
+    scratch.wrapped = 0;
     let genome_shift: u64 = rand.next_range(0..(genome.span));
 
     for i in intv.iter() {
@@ -83,45 +171,117 @@ fn circle_intervals(intv: &Lapper<u64, u64>, genome: &GenomeShift, per_chrom: bo
 
         if new_start >= upper {
             ret.push(Iv {
-                start: new_start - upper,
-                stop: new_end - upper,
-                val: 0,
+                start: new_start - (upper - lower),
+                stop: new_end - (upper - lower),
+                val: i.val,
             });
         } else if new_end > upper {
-            ret.extend(vec![
-                Iv {
-                    start: new_start,
-                    stop: upper,
-                    val: 0,
-                },
-                Iv {
-                    start: lower,
-                    stop: new_end - upper,
-                    val: 0,
-                },
-            ]);
+            scratch.wrapped += 1;
+            ret.push(Iv {
+                start: new_start,
+                stop: upper,
+                val: i.val,
+            });
+            ret.push(Iv {
+                start: lower,
+                stop: lower + (new_end - upper),
+                val: i.val,
+            });
         } else {
             ret.push(Iv {
                 start: new_start,
                 stop: new_end,
-                val: 0,
+                val: i.val,
             });
         }
     }
-    ret
+}
+
+/// Randomly shift all intervals downstream with wrap-around, keeping them whole.
+/// Intervals crossing the end, another chromosome, or a masked region are moved forward to
+/// the next segment with room for them, wrapping around to the start when none has
+fn circle_whole_intervals(
+    intv: &Lapper<u64, u64>,
+    genome: &GenomeShift,
+    per_chrom: bool,
+    rand: &mut StdRand,
+    scratch: &mut Scratch,
+) {
+    scratch.wrapped = 0;
+    let genome_shift: u64 = rand.next_range(0..(genome.span));
+
+    scratch.intv.extend(intv.iter().map(|i| {
+        let (lower, upper, shift) = if per_chrom {
+            match genome.chrom.find(i.start, i.stop).next() {
+                Some(b) => (b.start, b.stop, genome_shift % b.val),
+                None => panic!("Interval @ ({}, {}) not hitting genome", i.start, i.stop),
+            }
+        } else {
+            (0, genome.span, genome_shift)
+        };
+        let len = i.stop - i.start;
+
+        let mut new_start: u64 = i.start + shift;
+        if new_start >= upper {
+            new_start -= upper - lower;
+        }
+        let placed = fit_segment(genome, new_start, len, lower, upper).unwrap_or_else(|| {
+            panic!(
+                "Interval @ ({}, {}) is longer than every segment it can be placed on",
+                i.start, i.stop
+            )
+        });
+        scratch.wrapped += (placed != new_start) as u64;
+        Iv {
+            start: placed,
+            stop: placed + len,
+            val: i.val,
+        }
+    }))
+}
+
+/// First start from `pos` within `lower..upper`, wrapping around to `lower` once, where `len` bases
+/// fit without crossing a segment's start. None when no segment is long enough
+fn fit_segment(
+    genome: &GenomeShift,
+    mut pos: u64,
+    len: u64,
+    lower: u64,
+    upper: u64,
+) -> Option<u64> {
+    let mut wrapped = false;
+    loop {
+        if pos + len > upper {
+            if wrapped {
+                return None;
+            }
+            wrapped = true;
+            pos = lower;
+            continue;
+        }
+        match genome.next_segment(pos, pos + len) {
+            Some(s) => pos = s,
+            None => return Some(pos),
+        }
+    }
 }
 
 /// Randomly move each interval to new position without overlapping them
-fn novl_intervals(intv: &Lapper<u64, u64>, genome: &GenomeShift, per_chrom: bool) -> Vec<Iv> {
-    let mut ret: Vec<Iv> = Vec::<Iv>::with_capacity(intv.len());
-
-    let spans = match per_chrom {
-        true => genome.chrom.intervals.clone(),
-        false => vec![Iv {
-            start: 0,
-            stop: genome.span,
-            val: 0,
-        }],
+fn novl_intervals(
+    intv: &Lapper<u64, u64>,
+    genome: &GenomeShift,
+    per_chrom: bool,
+    rand: &mut StdRand,
+    scratch: &mut Scratch,
+) {
+    let whole = [Iv {
+        start: 0,
+        stop: genome.span,
+        val: 0,
+    }];
+    let spans: &[Iv] = match per_chrom {
+        true => &genome.chrom.intervals,
+        false => &whole,
     };
 
     for subi in spans {
@@ -130,26 +290,171 @@ fn novl_intervals(intv: &Lapper<u64, u64>, genome: &GenomeShift, per_chrom: bool
             None => panic!("How are you using the gap_budget without making it first?"),
         };
 
-        let mut cur_intervals: Vec<(bool, u64)> = GapBreaks::new(m_gap).collect();
+        let cur_intervals = &mut scratch.pieces;
+        cur_intervals.clear();
         cur_intervals.extend(
             intv.find(subi.start, subi.stop)
-                .map(|i| (true, i.stop - i.start)),
+                .map(|i| (i.stop - i.start, i.val)),
         );
-        fastrand::shuffle(&mut cur_intervals);
-
-        let mut cur_pos = subi.start;
-        for i in cur_intervals {
-            if i.0 {
-                ret.push(Iv {
-                    start: cur_pos,
-                    stop: cur_pos + i.1,
-                    val: 0,
-                });
-            }
-            cur_pos += i.1;
+        fastrand::Rng::with_seed(rand.next_u64()).shuffle(cur_intervals);
+
+        match &genome.exclude {
+            None => place_novl(
+                subi.start,
+                m_gap,
+                cur_intervals.iter().copied(),
+                &mut scratch.gaps,
+                rand,
+                &mut scratch.intv,
+            ),
+            Some(e) => novl_stretches(subi, e, rand, scratch),
+        }
+    }
+}
+
+/// Place intervals of (length, val) in order from `start` with `gap` uncovered bases randomly split between them
+fn place_novl(
+    start: u64,
+    gap: u64,
+    pieces: impl ExactSizeIterator<Item = (u64, u64)>,
+    gaps: &mut GapBreaks,
+    rand: &mut StdRand,
+    ret: &mut Vec<Iv>,
+) {
+    let mut cur_pos = start;
+    let num = pieces.len();
+    for ((len, val), gap) in pieces.zip(gaps.sample(gap, num, rand)) {
+        cur_pos += gap;
+        ret.push(Iv {
+            start: cur_pos,
+            stop: cur_pos + len,
+            val,
+        });
+        cur_pos += len;
+    }
+}
+
+/// Place novl's shuffled intervals within the stretches of `span` between excluded regions.
+/// Each interval, longest first, goes to a random stretch with room left for it, picked in proportion
+/// to the stretches' lengths. Then each stretch's intervals are placed like in a span of their own
+fn novl_stretches(
+    span: &Iv,
+    exclude: &Lapper<u64, u64>,
+    rand: &mut StdRand,
+    scratch: &mut Scratch,
+) {
+    let stretches = &mut scratch.stretches;
+    stretches.clear();
+    let mut pos = span.start;
+    for e in exclude.find(span.start, span.stop) {
+        if e.start > pos {
+            stretches.push(Iv {
+                start: pos,
+                stop: e.start,
+                val: e.start - pos,
+            });
+        }
+        pos = std::cmp::max(pos, e.stop);
+    }
+    if span.stop > pos {
+        stretches.push(Iv {
+            start: pos,
+            stop: span.stop,
+            val: span.stop - pos,
+        });
+    }
+    scratch.cum_len.clear();
+    scratch.cum_len.extend(stretches.iter().scan(0, |tot, s| {
+        *tot += s.stop - s.start;
+        Some(*tot)
+    }));
+    let total = scratch.cum_len.last().copied().unwrap_or(0);
+
+    // stable so equal lengths stay shuffled
+    scratch.pieces.sort_by_key(|p| std::cmp::Reverse(p.0));
+    // a span that's all excluded has nowhere to pick from
+    let tries = if total > 0 { MAX_TRIES } else { 0 };
+    let assigned = &mut scratch.assigned;
+    assigned.clear();
+    for &(len, val) in scratch.pieces.iter() {
+        let picked = (0..tries)
+            .map(|_| {
+                let r = rand.next_range(0..total);
+                scratch.cum_len.partition_point(|&c| c <= r)
+            })
+            .find(|&j| stretches[j].val >= len)
+            .or_else(|| stretches.iter().position(|s| s.val >= len));
+        let Some(j) = picked else {
+            error!("no room for a {}bp interval outside of --exclude", len);
+            std::process::exit(1);
+        };
+        stretches[j].val -= len;
+        assigned.push((j, len, val));
+    }
+
+    // back to a random order within each stretch
+    fastrand::Rng::with_seed(rand.next_u64()).shuffle(assigned);
+    assigned.sort_by_key(|a| a.0);
+    for stretch in assigned.chunk_by(|a, b| a.0 == b.0) {
+        let s = &stretches[stretch[0].0];
+        place_novl(
+            s.start,
+            s.val,
+            stretch.iter().map(|&(_, len, val)| (len, val)),
+            &mut scratch.gaps,
+            rand,
+            &mut scratch.intv,
+        );
+    }
+}
+
+/// Randomly permute the genome's blocks, moving each interval with the block it starts in.
+/// Intervals at chromosome ends that don't fill a block stay in place
+fn block_intervals(
+    intv: &Lapper<u64, u64>,
+    genome: &GenomeShift,
+    per_chrom: bool,
+    rand: &mut StdRand,
+    scratch: &mut Scratch,
+) {
+    let blocks = match &genome.blocks {
+        Some(b) => b,
+        None => panic!("How are you using the blocks without making them first?"),
+    };
+    let order = &mut scratch.order;
+    order.clear();
+    order.extend(0..blocks.len());
+    let rng = fastrand::Rng::with_seed(rand.next_u64());
+    if per_chrom {
+        let mut first = 0;
+        for chrom in blocks.chunk_by(|a, b| a.val == b.val) {
+            rng.shuffle(&mut order[first..first + chrom.len()]);
+            first += chrom.len();
         }
+    } else {
+        rng.shuffle(order);
     }
-    ret
+
+    scratch.intv.extend(intv.iter().map(|i| {
+        let k = blocks.partition_point(|b| b.start <= i.start);
+        match k.checked_sub(1) {
+            Some(k) if i.start < blocks[k].stop => {
+                let (from, to) = (&blocks[k], &blocks[order[k]]);
+                let len = i.stop - i.start;
+                // intervals running off the end of their new block's chromosome are pulled back onto it
+                let new_position = std::cmp::min(
+                    to.start + (i.start - from.start),
+                    to.val.saturating_sub(len),
+                );
+                Iv {
+                    start: new_position,
+                    stop: new_position + len,
+                    val: i.val,
+                }
+            }
+            _ => i.clone(),
+        }
+    }))
 }
 
 /// Shift each interval a set amount. Intervals shifted to < 0 are trimmed/removed
@@ -170,9 +475,184 @@ pub fn shift_intervals(intv: &Lapper<u64, u64>, shift: i64) -> Lapper<u64, u64>
                 Iv {
                     start: new_start as u64,
                     stop: new_end as u64,
-                    val: 0,
+                    val: i.val,
                 }
             })
             .collect(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::tests::test_genome;
+    use crate::stats::looks_uniform;
+    use std::collections::{HashMap, HashSet};
+
+    /// First draws of streams `0..n` of `seed`
+    fn first_draws(seed: u64, n: u64) -> HashSet<u64> {
+        (0..n).map(|j| perm_rand(seed, j).next_u64()).collect()
+    }
+
+    #[test]
+    fn adjacent_seeds_dont_overlap() {
+        for seed in [0, 1, 41, u64::MAX - 1] {
+            let a = first_draws(seed, 10_000);
+            let b = first_draws(seed.wrapping_add(1), 10_000);
+            assert_eq!(a.len(), 10_000);
+            assert!(
+                a.is_disjoint(&b),
+                "seeds {} and {} share streams",
+                seed,
+                seed.wrapping_add(1)
+            );
+        }
+    }
+
+    #[test]
+    fn group_streams_dont_overlap() {
+        // group `c` of a run permutes with `stream_seed(seed, c)` as its seed
+        let mut seen = HashSet::new();
+        for seed in 0..4 {
+            for c in 0..4 {
+                seen.extend(first_draws(stream_seed(seed, c), 1_000));
+            }
+        }
+        assert_eq!(seen.len(), 16 * 1_000);
+    }
+
+    fn iv(start: u64, stop: u64) -> Iv {
+        Iv {
+            start,
+            stop,
+            val: 0,
+        }
+    }
+
+    /// c1 of 25bp with 8..13 masked, so 20bp joined at 8, and c2 of 12bp. `name` keeps tests' files apart
+    fn small_genome(name: &str) -> GenomeShift {
+        test_genome(name, "c1\t25\nc2\t12\n", "c1\t8\t13\n")
+    }
+
+    #[test]
+    fn novl_is_uniform() {
+        // c1's 3bp and 4bp intervals have 13 uncovered bases: 2 orders * C(15, 2) = 210 placements.
+        // c2's 2bp interval has 11
+        let mut genome = small_genome("novl_uniform");
+        let intv = Lapper::new(vec![iv(0, 3), iv(5, 9), iv(24, 26)]);
+        genome.make_gap_budget(&intv, &true);
+
+        let mut rand = StdRand::seed(48);
+        let mut scratch = Scratch::default();
+        let num = 21_000;
+        let (mut c1, mut c2) = (HashMap::new(), HashMap::new());
+        for _ in 0..num {
+            Randomizer::Novl.ize_into(&intv, &genome, true, &mut rand, &mut scratch);
+            let start_of = |len| {
+                scratch
+                    .intv
+                    .iter()
+                    .find(|i| i.stop - i.start == len)
+                    .unwrap()
+            };
+            let (short, long, other) = (start_of(3), start_of(4), start_of(2));
+            assert!(short.stop <= 20 && long.stop <= 20);
+            assert!(short.stop <= long.start || long.stop <= short.start);
+            assert!(other.start >= 20 && other.stop <= 32);
+            *c1.entry((short.start, long.start)).or_insert(0) += 1;
+            *c2.entry(other.start).or_insert(0) += 1;
+        }
+        assert!(looks_uniform(c1.values().copied(), 210, num));
+        assert!(looks_uniform(c2.values().copied(), 11, num));
+    }
+
+    /// Whether `i` lies within one stretch of `genome` that's contiguous in the reference
+    fn in_one_segment(genome: &GenomeShift, i: &Iv) -> bool {
+        i.stop <= genome.span && genome.segments.iter().all(|&s| s <= i.start || s >= i.stop)
+    }
+
+    #[test]
+    fn circle_whole_stays_in_segments() {
+        // c1 joins at 8 where 8..13 is masked and c2 is too short to hold any interval
+        let genome = test_genome("circle_whole", "c1\t25\nc2\t2\nc3\t12\n", "c1\t8\t13\n");
+        let intv = Lapper::new(vec![iv(0, 3), iv(10, 16), iv(24, 30)]);
+        let lengths = |intv: &[Iv]| {
+            let mut l: Vec<u64> = intv.iter().map(|i| i.stop - i.start).collect();
+            l.sort_unstable();
+            l
+        };
+
+        let mut rand = StdRand::seed(47);
+        let mut scratch = Scratch::default();
+        for per_chrom in [false, true] {
+            for _ in 0..1_000 {
+                Randomizer::CircleWhole.ize_into(
+                    &intv,
+                    &genome,
+                    per_chrom,
+                    &mut rand,
+                    &mut scratch,
+                );
+                assert_eq!(lengths(&scratch.intv), lengths(&intv.intervals));
+                for i in &scratch.intv {
+                    assert!(
+                        in_one_segment(&genome, i),
+                        "({}, {}) crosses a segment",
+                        i.start,
+                        i.stop
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn placements_avoid_exclude() {
+        let mut genome = small_genome("exclude");
+        let exclude = Lapper::new(vec![iv(2, 6), iv(14, 17), iv(24, 28)]);
+        genome.exclude = Some(exclude.clone());
+        let intv = Lapper::new(vec![iv(0, 2), iv(9, 12), iv(21, 23)]);
+
+        let mut rand = StdRand::seed(49);
+        let mut scratch = Scratch::default();
+        for random in [Randomizer::Shuffle, Randomizer::Novl] {
+            for per_chrom in [false, true] {
+                genome.make_gap_budget(&intv, &per_chrom);
+                for _ in 0..1_000 {
+                    random.ize_into(&intv, &genome, per_chrom, &mut rand, &mut scratch);
+                    assert_eq!(scratch.intv.len(), intv.len());
+                    for i in &scratch.intv {
+                        assert!(
+                            exclude.find(i.start, i.stop).next().is_none(),
+                            "({}, {}) is on an excluded region",
+                            i.start,
+                            i.stop
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shuffle_stays_in_segments() {
+        let genome = small_genome("shuffle_segments");
+        let intv = Lapper::new(vec![iv(0, 3), iv(9, 15), iv(22, 30)]);
+
+        let mut rand = StdRand::seed(50);
+        let mut scratch = Scratch::default();
+        for per_chrom in [false, true] {
+            for _ in 0..1_000 {
+                Randomizer::Shuffle.ize_into(&intv, &genome, per_chrom, &mut rand, &mut scratch);
+                for i in &scratch.intv {
+                    assert!(
+                        in_one_segment(&genome, i),
+                        "({}, {}) crosses a segment",
+                        i.start,
+                        i.stop
+                    );
+                }
+            }
+        }
+    }
+}
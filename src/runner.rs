@@ -0,0 +1,368 @@
+//! Steps shared between subcommands
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use clap::ValueEnum;
+use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use rust_lapper::Lapper;
+use serde::Serialize;
+use tinyrand::Rand;
+use tinyrand_std::ClockSeed;
+
+use crate::cli::{GenomeArgs, PermArgs, RandomArgs};
+use crate::io::{
+    read_bed, read_genome, read_mask, write_bed, GenomeShift, InputStats, Iv, MaskShift, Payload,
+};
+use crate::overlappers::{max_len, Overlapper};
+use crate::randomizers::{perm_rand, Randomizer, Scratch};
+use crate::stats::PermTest;
+
+/// How a result was made: tool version, command, inputs and run details
+#[derive(Serialize)]
+pub struct Provenance {
+    pub version: String,
+    pub command: Vec<String>,
+    pub genome: InputStats,
+    pub mask: Option<InputStats>,
+    pub exclude: Option<InputStats>,
+    pub inputs: Vec<InputStats>,
+    pub threads: usize,
+    pub seed: Option<u64>,
+    // seconds from loading the genome until `finish`
+    pub runtime: f64,
+    #[serde(skip)]
+    start: Instant,
+}
+
+impl Provenance {
+    /// Record the runtime
+    pub fn finish(&mut self) {
+        self.runtime = self.start.elapsed().as_secs_f64();
+        log_peak_memory("of run");
+    }
+}
+
+/// Peak resident memory of the process in bytes, where the OS reports it (Linux)
+pub fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kb = status.lines().find_map(|l| l.strip_prefix("VmHWM:"))?;
+    kb.trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|kb| kb * 1024)
+}
+
+/// Log the peak memory so far
+pub fn log_peak_memory(when: &str) {
+    if let Some(bytes) = peak_memory() {
+        info!("peak memory {}: {:.1} MB", when, bytes as f64 / 1e6);
+    }
+}
+
+/// Read the genome and its mask, starting the run's provenance
+pub fn load_genome(args: &GenomeArgs) -> (GenomeShift, Option<MaskShift>, Provenance) {
+    let start = Instant::now();
+    let (mask, mask_stats) = match args.mask.as_ref().map(|p| read_mask(p)) {
+        Some((m, s)) => (Some(m), Some(s)),
+        None => (None, None),
+    };
+    let (genome, genome_stats) = read_genome(&args.genome, &mask);
+    let prov = Provenance {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        command: std::env::args().collect(),
+        genome: genome_stats,
+        mask: mask_stats,
+        exclude: None,
+        inputs: vec![],
+        threads: 1,
+        seed: None,
+        runtime: 0.0,
+        start,
+    };
+    (genome, mask, prov)
+}
+
+/// Read the `--exclude` regions into the genome. Masked bases are already excluded so regions
+/// overlapping the mask keep their unmasked bases
+pub fn load_exclude(
+    random: &RandomArgs,
+    genome: &mut GenomeShift,
+    mask: &Option<MaskShift>,
+    prov: &mut Provenance,
+) {
+    if let Some(path) = &random.exclude {
+        let (mut intv, stats) = read_bed(path, genome, mask, Payload::Empty, true);
+        intv.merge_overlaps();
+        genome.exclude = Some(intv);
+        prov.exclude = Some(stats);
+    }
+}
+
+/// Read a bed file holding `payload`, merging its overlaps unless told not to.
+/// Scores and a bedGraph's signal are never merged since merging keeps only the first region's value
+pub fn load_bed(
+    file: &Path,
+    genome: &GenomeShift,
+    mask: &Option<MaskShift>,
+    no_merge: bool,
+    payload: Payload,
+) -> (Lapper<u64, u64>, InputStats) {
+    let (mut intv, stats) = read_bed(file, genome, mask, payload, false);
+    if !no_merge & !matches!(payload, Payload::Score | Payload::Signal) {
+        info!("merging overlaps");
+        intv.merge_overlaps();
+    }
+    log_peak_memory(&format!("after loading {}", file.display()));
+    (intv, stats)
+}
+
+/// Split intervals holding [`Payload::Name`] into one set per name,
+/// merging each set's overlaps unless told not to
+pub fn split_groups(
+    intv: Lapper<u64, u64>,
+    num_groups: usize,
+    no_merge: bool,
+) -> Vec<Lapper<u64, u64>> {
+    let mut groups: Vec<Vec<Iv>> = vec![vec![]; num_groups];
+    for i in intv.intervals {
+        groups[i.val as usize].push(Iv { val: 0, ..i });
+    }
+    groups
+        .into_iter()
+        .map(|g| {
+            let mut g = Lapper::new(g);
+            if !no_merge {
+                g.merge_overlaps();
+            }
+            g
+        })
+        .collect()
+}
+
+/// Randomized/tested inputs after swapping and building any gap budget
+pub struct Prepared {
+    pub genome: Arc<GenomeShift>,
+    pub a_intv: Arc<Lapper<u64, u64>>,
+    pub b_intv: Arc<Lapper<u64, u64>>,
+    /// length of B's longest interval
+    pub b_max_len: u64,
+    pub swapped: bool,
+}
+
+/// Swap A and B so the shorter is randomized (when the counter allows) and make the genome ready for the randomizer
+pub fn prepare(
+    mut genome: GenomeShift,
+    mut a_intv: Arc<Lapper<u64, u64>>,
+    mut b_intv: Arc<Lapper<u64, u64>>,
+    random: &RandomArgs,
+    perm: &PermArgs,
+) -> Prepared {
+    // --exclude restricts where A, not B, is placed
+    let no_swap = perm.no_swap | !perm.count.swappable() | random.exclude.is_some();
+    let swapped = if !no_swap & (a_intv.len() > b_intv.len()) {
+        info!("swapping A for shorter B");
+        std::mem::swap(&mut a_intv, &mut b_intv);
+        true
+    } else {
+        false
+    };
+    ready_genome(&mut genome, &a_intv, random);
+    // Won't need to change again. Can pass pointers to threads
+    Prepared {
+        b_max_len: max_len(&b_intv.intervals),
+        genome: Arc::new(genome),
+        a_intv,
+        b_intv,
+        swapped,
+    }
+}
+
+/// Build what the randomizer needs from the genome and the randomized intervals
+pub fn ready_genome(genome: &mut GenomeShift, a_intv: &Lapper<u64, u64>, random: &RandomArgs) {
+    match random.random {
+        Randomizer::Shuffle => {
+            let too_long = count_too_long(genome, a_intv, random.per_chrom);
+            if too_long > 0 {
+                warn!(
+                    "{} intervals are longer than every chromosome (or stretch between masked regions) they can be placed on and may be placed across their ends",
+                    too_long
+                );
+            }
+        }
+        Randomizer::CircleWhole => {
+            let too_long = count_too_long(genome, a_intv, random.per_chrom);
+            if too_long > 0 {
+                error!(
+                    "{} intervals are longer than every chromosome (or stretch between masked regions) they can be placed on and can't be kept whole. Use `--random circle`",
+                    too_long
+                );
+                std::process::exit(1);
+            }
+        }
+        Randomizer::Novl => genome.make_gap_budget(a_intv, &random.per_chrom),
+        Randomizer::Block => genome.make_blocks(random.block_size),
+        _ => (),
+    }
+}
+
+/// Number of intervals longer than every segment they can be placed on
+fn count_too_long(genome: &GenomeShift, a_intv: &Lapper<u64, u64>, per_chrom: bool) -> usize {
+    let longest = genome.longest_segment(None);
+    a_intv
+        .iter()
+        .filter(|i| {
+            let chrom = genome.chrom.find(i.start, i.stop).next();
+            let longest = match (per_chrom, chrom) {
+                (true, Some(c)) => genome.longest_segment(Some(c.start)),
+                _ => longest,
+            };
+            i.stop - i.start > longest
+        })
+        .count()
+}
+
+/// Seed of a run: the one given or one from the clock
+pub fn pick_seed(seed: Option<u64>) -> u64 {
+    seed.unwrap_or_else(|| ClockSeed.next_u64())
+}
+
+/// Count overlaps of randomizations `start..num_times` of A with B.
+/// Randomization `j` is seeded by `seed` and `j` so results don't depend on `--threads`.
+/// The first `dump_n` randomized A sets are written to `dump_dir`
+pub fn permute(
+    data: &Prepared,
+    random: &RandomArgs,
+    perm: &PermArgs,
+    seed: u64,
+    (start, num_times): (u32, u32),
+    dump_n: u32,
+    dump_dir: &Path,
+) -> Vec<u64> {
+    let (count, method) = (perm.count, perm.count_method);
+    let (randomizer, per_chrom) = (random.random, random.per_chrom);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(perm.num_threads())
+        .build()
+        .unwrap();
+
+    let pb = ProgressBar::new((num_times - start).into());
+    pb.set_style(
+        ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
+        )
+        .unwrap()
+        .progress_chars("##-"),
+    );
+
+    let wrapped = AtomicU64::new(0);
+    let perm_counts: Vec<u64> = pool.install(|| {
+        (start..num_times)
+            .into_par_iter()
+            .progress_with(pb.clone())
+            .map_init(Scratch::default, |scratch, j| {
+                let mut rand = perm_rand(seed, j as u64);
+                randomizer.ize_into(&data.a_intv, &data.genome, per_chrom, &mut rand, scratch);
+                if j < dump_n {
+                    let path = dump_dir.join(format!("perm_{}.bed", j));
+                    let r_intv = Lapper::new(scratch.intv.clone());
+                    if let Err(e) = write_bed(&path, &r_intv, &data.genome) {
+                        warn!("unable to write {}: {}", path.display(), e);
+                    }
+                }
+                wrapped.fetch_add(scratch.wrapped, Ordering::Relaxed);
+                scratch.intv.sort_unstable_by_key(|i| i.start);
+                count.ovl_sorted(
+                    &scratch.intv,
+                    &data.b_intv.intervals,
+                    data.b_max_len,
+                    method,
+                )
+            })
+            .collect()
+    });
+    pb.finish_and_clear();
+    let action = match randomizer {
+        Randomizer::Circle => Some("split at the end"),
+        Randomizer::CircleWhole => Some("moved past an end or masked region"),
+        _ => None,
+    };
+    if let Some(action) = action {
+        info!(
+            "{:.2} of {} intervals per permutation were {}",
+            wrapped.into_inner() as f64 / perm_counts.len() as f64,
+            data.a_intv.len(),
+            action
+        );
+    }
+    perm_counts
+}
+
+/// A test's row of the --summary-tsv. See [`crate::io::SUMMARY_HEADER`]
+#[allow(clippy::too_many_arguments)]
+pub fn summary_row(
+    bed_a: &Path,
+    bed_b: &Path,
+    test: &PermTest,
+    random: &RandomArgs,
+    count: Overlapper,
+    a_count: usize,
+    b_count: usize,
+    swapped: bool,
+) -> String {
+    [
+        bed_a.display().to_string(),
+        bed_b.display().to_string(),
+        test.observed.to_string(),
+        test.mean.to_string(),
+        test.std_dev.to_string(),
+        test.z_score.to_string(),
+        test.p_val.to_string(),
+        test.alt.to_string(),
+        test.num_perms.to_string(),
+        random
+            .random
+            .to_possible_value()
+            .unwrap()
+            .get_name()
+            .to_string(),
+        count.to_possible_value().unwrap().get_name().to_string(),
+        random.per_chrom.to_string(),
+        a_count.to_string(),
+        b_count.to_string(),
+        swapped.to_string(),
+    ]
+    .join("\t")
+}
+
+/// Config hash of a test for checking a checkpoint belongs to it.
+/// Covers the inputs' contents and everything that changes what a permutation counts
+pub fn config_hash(prov: &Provenance, random: &RandomArgs, perm: &PermArgs) -> String {
+    let mut ctx = md5::Context::new();
+    ctx.consume(&prov.genome.md5);
+    if let Some(m) = &prov.mask {
+        ctx.consume(&m.md5);
+    }
+    if let Some(e) = &prov.exclude {
+        ctx.consume(&e.md5);
+    }
+    for i in &prov.inputs {
+        ctx.consume(&i.md5);
+    }
+    ctx.consume(format!(
+        "{}:{}:{}:{}:{}",
+        random.random.to_possible_value().unwrap().get_name(),
+        random.per_chrom,
+        random.no_merge,
+        perm.count.to_possible_value().unwrap().get_name(),
+        perm.no_swap
+    ));
+    if let Some(b) = random.block_size() {
+        ctx.consume(format!(":{}", b));
+    }
+    format!("{:x}", ctx.compute())
+}
@@ -1,11 +1,11 @@
 use rust_lapper::Lapper;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::overlappers::Overlapper;
+use crate::overlappers::{max_len, CountMethod, Overlapper};
 use crate::randomizers::shift_intervals;
 
 /// Creates and holds permutation test results
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct PermTest {
     pub observed: u64,
     pub num_perms: f64,
@@ -59,11 +59,13 @@ impl PermTest {
 }
 
 /// Creates and holds local z-score results
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct LocalZscore {
     pub shifts: Vec<f64>,
     pub window: i64,
     pub step: u64,
+    /// observed intersections at each shift
+    pub observed: Vec<u64>,
 }
 
 impl LocalZscore {
@@ -71,18 +73,31 @@ impl LocalZscore {
         a_intv: &Lapper<u64, u64>,
         b_intv: &Lapper<u64, u64>,
         count: Overlapper,
+        method: CountMethod,
         window: i64,
         step: u64,
         test: &PermTest,
     ) -> Self {
-        let shifts: Vec<f64> = (-window..window)
+        let b_max_len = max_len(&b_intv.intervals);
+        let observed: Vec<u64> = (-window..window)
             .step_by(step as usize)
             .map(|i| {
-                let observed = count.ovl(&shift_intervals(a_intv, i), b_intv);
-                if (observed == 0) & (test.mean == 0.0) {
+                let shifted = shift_intervals(a_intv, i);
+                count.ovl_sorted(&shifted.intervals, &b_intv.intervals, b_max_len, method)
+            })
+            .collect();
+        LocalZscore::from_observed(observed, window, step, test)
+    }
+
+    /// Calculate z-scores of each shift's observed intersections against a test's permutations
+    pub fn from_observed(observed: Vec<u64>, window: i64, step: u64, test: &PermTest) -> Self {
+        let shifts: Vec<f64> = observed
+            .iter()
+            .map(|&o| {
+                if (o == 0) & (test.mean == 0.0) {
                     0.0
                 } else {
-                    ((observed as f64) - test.mean) / test.std_dev
+                    ((o as f64) - test.mean) / test.std_dev
                 }
             })
             .collect();
@@ -90,6 +105,27 @@ impl LocalZscore {
             shifts,
             window,
             step,
+            observed,
         }
     }
 }
+
+/// Whether `counts` of `num` draws over `num_cells` equally likely cells pass a chi-square test
+/// of uniformity at p=0.001. Cells never drawn are left out of `counts`
+#[cfg(test)]
+pub fn looks_uniform(
+    counts: impl ExactSizeIterator<Item = u64>,
+    num_cells: usize,
+    num: u64,
+) -> bool {
+    let expected = num as f64 / num_cells as f64;
+    let seen = counts.len();
+    let mut chi: f64 = counts
+        .map(|c| (c as f64 - expected).powi(2) / expected)
+        .sum();
+    chi += num_cells.saturating_sub(seen) as f64 * expected;
+    // critical value by the Wilson-Hilferty approximation
+    let df = (num_cells - 1) as f64;
+    let critical = df * (1.0 - 2.0 / (9.0 * df) + 3.09 * (2.0 / (9.0 * df)).sqrt()).powi(3);
+    seen <= num_cells && chi < critical
+}
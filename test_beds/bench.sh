@@ -0,0 +1,44 @@
+#Times permutations of synthetic beds with each randomizer
+#usage: bench.sh [regioners] [baseline regioners]
+#Giving a baseline (e.g. a build of an older commit) times it beside the first for comparison
+DIR="$( cd "$( dirname "${BASH_SOURCE[0]}" )" && pwd )"
+BIN=${1:-$DIR/../target/release/regioners}
+BASE=$2
+NUM=${NUM:-1000}
+TMP=$(mktemp -d)
+trap "rm -rf $TMP" EXIT
+
+# Random regions of `len`bp spread over the genome proportional to chromosome size
+make_bed() {
+    awk -v n=$1 -v len=$2 -v seed=$3 'BEGIN {srand(seed)}
+        {chrom[NR] = $1; size[NR] = $2; total += $2}
+        END {
+            for (c = 1; c <= NR; c++) {
+                m = int(n * size[c] / total)
+                for (i = 0; i < m; i++) {
+                    s = int(rand() * (size[c] - len))
+                    print chrom[c] "\t" s "\t" s + len
+                }
+            }
+        }' $DIR/grch38.genome.txt | sort -k1,1 -k2,2n
+}
+
+make_bed 30000 500 1 > $TMP/a.bed
+make_bed 1000000 50 2 > $TMP/b.bed
+
+TIMEFORMAT=%R
+run() {
+    { time $1 test -g $DIR/grch38.genome.txt -A $TMP/a.bed -B $TMP/b.bed -n $NUM --seed 1 --no-plot \
+        -o $TMP/out.json ${@:2} 2> /dev/null ; } 2>&1
+}
+
+printf "params\tseconds"
+[ -n "$BASE" ] && printf "\tbaseline"
+printf "\n"
+for params in "--random shuffle" "--random shuffle --per-chrom" "--random circle" "--random circle --per-chrom" \
+              "--random circle-whole" "--random circle-whole --per-chrom" \
+              "--random novl" "--random novl --per-chrom" "--random block" "--random block --per-chrom"; do
+    printf "%s\t%s" "$params" "$(run $BIN $params)"
+    [ -n "$BASE" ] && printf "\t%s" "$(run $BASE $params)"
+    printf "\n"
+done